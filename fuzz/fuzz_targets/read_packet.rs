@@ -0,0 +1,10 @@
+#![no_main]
+//everything a client sends goes through here, so no packet should be able to crash it
+use std::collections::VecDeque;
+
+use libfuzzer_sys::fuzz_target;
+use soaprun::packets::read_packet;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = read_packet(&mut VecDeque::from([data.to_vec()]));
+});
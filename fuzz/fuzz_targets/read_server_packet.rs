@@ -0,0 +1,10 @@
+#![no_main]
+//the bot shouldn't crash on whatever a (possibly broken) server sends it either
+use std::collections::VecDeque;
+
+use libfuzzer_sys::fuzz_target;
+use soaprun::packets::read_server_packet;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = read_server_packet(&mut VecDeque::from([data.to_vec()]));
+});
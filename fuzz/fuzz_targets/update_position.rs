@@ -0,0 +1,60 @@
+#![no_main]
+//arbitrary movements from an arbitrary starting point, checked against the November 2010 map
+//the input is a flags byte, the starting position, then any number of movements (each position is two little endian i16s)
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use soapdispenser::server::{Client, ServerConfig, SoaprunServer};
+use soaprun::position::Position;
+use soaprun::soaprunners::{SoaprunnerColors, SoaprunnerItems, SoaprunnerSprites};
+
+fn server() -> &'static SoaprunServer {
+    static SERVER : OnceLock<&'static SoaprunServer> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let recreations = concat!(env!("CARGO_MANIFEST_DIR"), "/../recreations");
+        //no limits, so long movements get all the way to the tile checks
+        let config: ServerConfig = serde_json::from_value(serde_json::json!({
+            "room_verification_bounds": "None",
+            "room_verification_mode": "Tiles",
+            "room_directory": format!("{recreations}/2010_11_13"),
+            "entity_path": format!("{recreations}/2010_11_13/entities.json"),
+            "attributes_path": format!("{recreations}/map.attributes"),
+            "connection_timeout": 0,
+            "idle_timeout": 0,
+            "max_player_movement_nodes_per_packet": 0,
+            "max_player_distance_per_movement_node": 0,
+            "max_player_distance_per_packet": 0,
+            "max_players": 1,
+            "address": "127.0.0.1:0",
+            "dispatch": null
+        })).unwrap();
+        SoaprunServer::new(&config).unwrap()
+    })
+}
+
+fn read_position(data: &[u8]) -> Position {
+    Position {
+        x: i16::from_le_bytes([data[0], data[1]]),
+        y: i16::from_le_bytes([data[2], data[3]])
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, data)) = data.split_first() else {
+        return
+    };
+    let Some((start, data)) = data.split_first_chunk::<4>() else {
+        return
+    };
+    let mut client = Client::new(0, SoaprunnerColors::Green);
+    client.has_moved = flags & 1 != 0;
+    if flags & 2 != 0 {
+        client.soaprunner.sprite = SoaprunnerSprites::Ghost;
+    }
+    if flags & 4 != 0 {
+        client.soaprunner.items.insert(SoaprunnerItems::Shield);
+    }
+    client.soaprunner.movements = vec![read_position(start)];
+    let movements = Vec::from_iter(data.chunks_exact(4).map(read_position));
+    let _ = Client::update_position(&mut client, &movements, server());
+});
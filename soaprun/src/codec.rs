@@ -0,0 +1,275 @@
+use std::io::{Error, ErrorKind};
+
+use super::map_attributes::MapAttributes;
+use super::packets::{Capabilities, Weather};
+use super::position::Position;
+use super::rooms::{Room, RoomCoordinates, CLIENT_ROOM_HEIGHT, CLIENT_ROOM_WIDTH};
+use super::soaprunners::{Soaprunner, SoaprunnerColors, SoaprunnerItems, SoaprunnerSprites};
+use super::units::{Unit, UnitStates, UnitTypes};
+
+//everything is little endian, and there's never any padding unless a struct says so
+pub trait Encode {
+    //only fails when something's too big for the protocol (like more than 255 movements)
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error>;
+}
+pub trait Decode : Sized {
+    //takes what it needs off the front of data, leaving the rest for whatever comes next
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    //how many more bytes were needed
+    MissingData(usize),
+    //the bytes were there, but they don't mean anything (unknown enum values, bad UTF-8, etc.)
+    InvalidValue,
+    UnknownType([u8; 4])
+}
+
+//takes the next n bytes off the front of data
+pub fn take<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    let (taken, rest) = data.split_at_checked(n).ok_or_else(|| DecodeError::MissingData(n - data.len()))?;
+    *data = rest;
+    Ok(taken)
+}
+pub fn too_big() -> Error {
+    Error::from(ErrorKind::InvalidInput)
+}
+
+impl Encode for u8 {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        data.push(*self);
+        Ok(())
+    }
+}
+impl Decode for u8 {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(data, 1)?[0])
+    }
+}
+impl Encode for i8 {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        data.push(*self as u8);
+        Ok(())
+    }
+}
+impl Decode for i8 {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(u8::decode(data)? as i8)
+    }
+}
+impl<const N : usize> Encode for [u8; N] {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        data.extend_from_slice(self);
+        Ok(())
+    }
+}
+impl<const N : usize> Decode for [u8; N] {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(data, N)?.try_into().unwrap())
+    }
+}
+macro_rules! impl_codec_for_int {
+    ($($int:ty),*) => {$(
+        impl Encode for $int {
+            fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+                data.extend_from_slice(&self.to_le_bytes());
+                Ok(())
+            }
+        }
+        impl Decode for $int {
+            fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+                Ok(<$int>::from_le_bytes(Decode::decode(data)?))
+            }
+        }
+    )*};
+}
+impl_codec_for_int!(u16, i16, u32);
+
+//the enums all have a TryFrom<u8> already
+macro_rules! impl_codec_for_enum {
+    ($($enum:ty),*) => {$(
+        impl Encode for $enum {
+            fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+                data.push(*self as u8);
+                Ok(())
+            }
+        }
+        impl Decode for $enum {
+            fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+                u8::decode(data)?.try_into().map_err(|_| DecodeError::InvalidValue)
+            }
+        }
+    )*};
+}
+impl_codec_for_enum!(SoaprunnerSprites, SoaprunnerColors, UnitStates, UnitTypes, Weather);
+
+impl Encode for SoaprunnerItems {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.bits().encode(data)
+    }
+}
+impl Decode for SoaprunnerItems {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        SoaprunnerItems::from_bits(u8::decode(data)?).ok_or(DecodeError::InvalidValue)
+    }
+}
+
+//unknown capabilities are from some newer client, and just get ignored
+impl Encode for Capabilities {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.bits().encode(data)
+    }
+}
+impl Decode for Capabilities {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Capabilities::from_bits_truncate(u32::decode(data)?))
+    }
+}
+
+impl Encode for Position {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.x.encode(data)?;
+        self.y.encode(data)
+    }
+}
+impl Decode for Position {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Position { x: i16::decode(data)?, y: i16::decode(data)? })
+    }
+}
+
+//a list of movements, starting with how many there are
+impl Encode for [Position] {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        u8::try_from(self.len()).map_err(|_| too_big())?.encode(data)?;
+        for m in self {
+            m.encode(data)?;
+        }
+        Ok(())
+    }
+}
+impl Decode for Vec<Position> {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        let length = u8::decode(data)? as usize;
+        //check everything's there first, so the error says how long the whole list should've been
+        let mut movements = take(data, length * 4)?;
+        (0..length).map(|_| Position::decode(&mut movements)).collect()
+    }
+}
+
+impl Encode for RoomCoordinates {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.x.encode(data)?;
+        self.y.encode(data)
+    }
+}
+impl Decode for RoomCoordinates {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(RoomCoordinates { x: i8::decode(data)?, y: i8::decode(data)? })
+    }
+}
+
+impl Encode for Room {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.data.encode(data)
+    }
+}
+impl Decode for Room {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Room { data: <[u8; CLIENT_ROOM_WIDTH * CLIENT_ROOM_HEIGHT]>::decode(data)? })
+    }
+}
+
+impl Encode for MapAttributes {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.width.encode(data)?;
+        self.height.encode(data)?;
+        data.extend_from_slice(&self.attributes);
+        Ok(())
+    }
+}
+impl Decode for MapAttributes {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        let width = u16::decode(data)?;
+        let height = u16::decode(data)?;
+        let attributes = take(data, width as usize * height as usize)?.to_vec();
+        Ok(MapAttributes { width, height, attributes })
+    }
+}
+
+//everything after the index, which is written by the Flds packet itself
+impl Encode for Soaprunner {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.teleport_trigger.encode(data)?;
+        self.sprite.encode(data)?;
+        self.color.encode(data)?;
+        self.items.encode(data)?;
+        self.movements.encode(data)
+    }
+}
+impl Decode for Soaprunner {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Soaprunner {
+            teleport_trigger: u8::decode(data)?,
+            sprite: SoaprunnerSprites::decode(data)?,
+            color: SoaprunnerColors::decode(data)?,
+            items: SoaprunnerItems::decode(data)?,
+            movements: Vec::decode(data)?
+        })
+    }
+}
+
+//same as soaprunners, the index comes from the packet
+impl Encode for Unit {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.teleport_trigger.encode(data)?;
+        self.unit_state.encode(data)?;
+        self.unit_type.encode(data)?;
+        self.direction.encode(data)?;
+        self.movements.encode(data)
+    }
+}
+impl Decode for Unit {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Unit {
+            teleport_trigger: u8::decode(data)?,
+            unit_state: UnitStates::decode(data)?,
+            unit_type: UnitTypes::decode(data)?,
+            direction: u8::decode(data)?,
+            movements: Vec::decode(data)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::position::Position;
+    use crate::soaprunners::{Soaprunner, SoaprunnerColors, SoaprunnerItems, SoaprunnerSprites};
+    use super::{Decode, DecodeError, Encode};
+
+    #[test]
+    fn pieces_round_trip() {
+        let soaprunner = Soaprunner {
+            teleport_trigger: 7,
+            sprite: SoaprunnerSprites::Ghost,
+            color: SoaprunnerColors::Yellow,
+            items: SoaprunnerItems::Sword | SoaprunnerItems::Crown,
+            movements: vec![Position { x: -1, y: 2 }, Position { x: 300, y: 2 }]
+        };
+        let mut data = Vec::new();
+        soaprunner.encode(&mut data).unwrap();
+        assert_eq!(data, b"\x07\x04\x03\x03\x02\xff\xff\x02\x00\x2c\x01\x02\x00");
+        let mut rest = &data[..];
+        let decoded = Soaprunner::decode(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(decoded.movements, soaprunner.movements);
+        assert!(matches!(decoded.sprite, SoaprunnerSprites::Ghost));
+
+        //a ghost with an item that doesn't exist
+        assert_eq!(Soaprunner::decode(&mut &b"\x00\x04\x00\x08\x00"[..]).err(), Some(DecodeError::InvalidValue));
+        //three movements, but only one is there
+        assert_eq!(Vec::<Position>::decode(&mut &b"\x03\x01\x00\x01\x00"[..]), Err(DecodeError::MissingData(8)));
+        assert!(vec![Position { x: 0, y: 0 }; 256].encode(&mut Vec::new()).is_err());
+    }
+}
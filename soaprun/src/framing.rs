@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::packets::MIN_PACKET_LENGTH;
+
+//anything packets can be sent over, one whole packet (type and body) at a time
+pub trait PacketStream {
+    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error>;
+    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error>;
+}
+
+//how the stock client frames packets over TCP: a u32 length, then the packet
+pub struct LengthPrefixed<T> {
+    inner: T,
+    //packets going to the server are a lot smaller than the ones coming from it
+    max_length: usize
+}
+impl<T> LengthPrefixed<T> {
+    pub fn new(inner: T, max_length: usize) -> LengthPrefixed<T> {
+        LengthPrefixed { inner, max_length }
+    }
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+}
+impl<T : Read + Write> PacketStream for LengthPrefixed<T> {
+    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
+        let mut length_buff = [0; 4];
+        self.inner.read_exact(&mut length_buff)?;
+        let length = u32::from_le_bytes(length_buff) as usize;
+        if length < MIN_PACKET_LENGTH || self.max_length < length {
+            return Err(io::Error::from(io::ErrorKind::OutOfMemory));
+        }
+
+        let mut data_buff = vec![0; length];
+        self.inner.read_exact(&mut data_buff)?;
+        Ok(data_buff)
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error> {
+        self.inner.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&packet)
+    }
+}
+
+//a loopback, whatever gets written is read back in order
+//handy for tools that only want to encode/decode packets
+impl PacketStream for VecDeque<Vec<u8>> {
+    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
+        self.pop_front().ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error> {
+        self.push_back(packet);
+        Ok(())
+    }
+}
@@ -2,5 +2,7 @@ pub mod rooms;
 pub mod map_attributes;
 pub mod soaprunners;
 pub mod units;
+pub mod codec;
+pub mod framing;
 pub mod packets;
-pub mod position;
\ No newline at end of file
+pub mod position;
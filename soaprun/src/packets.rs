@@ -0,0 +1,840 @@
+use std::io::Error;
+
+use bitflags::bitflags;
+use thiserror::Error;
+
+use super::framing::PacketStream;
+use super::codec::{take, too_big, Decode, DecodeError, Encode};
+use super::map_attributes::MapAttributes;
+use super::position::Position;
+use super::rooms::*;
+use super::soaprunners::*;
+use super::units::{Unit, UnitTypes};
+
+pub const CONNECTION_TEST_DATA_SIZE : usize = 508;
+pub enum ClientPackets {
+    ProtocolRequest {
+        game_version: u16
+    },
+    MapAttributeRequest,
+    RoomRequest {
+        coords: RoomCoordinates
+    },
+    MyPosition {
+        movements: Vec<Position>
+    },
+    MakeCorpse {
+        position: Position
+    },
+    ConnectionTest {
+        data: [u8; CONNECTION_TEST_DATA_SIZE]
+    },
+    LogDebugMessage {
+        message: String
+    },
+    Bye,
+    HitNonPlayerUnit {
+        index: u8,
+        movements: Vec<Position>
+    },
+    Heaven {
+        movements: Vec<Position>
+    },
+    ChangeColor {
+        color: u8,
+        movements: Vec<Position>
+    },
+    DrawOnField {
+        position: Position,
+        tile: u8,
+        movements: Vec<Position>
+    },
+    //not in the stock client, see Capabilities
+    Capabilities {
+        capabilities: Capabilities
+    },
+}
+
+impl ClientPackets {
+    pub fn packet_type(&self) -> &'static str {
+        let packet_type = match self {
+            ClientPackets::ProtocolRequest { .. } => &PACKET_TYPE_PROTOCOL,
+            ClientPackets::MapAttributeRequest => &PACKET_TYPE_MAP_ATTRIBUTES,
+            ClientPackets::RoomRequest { .. } => &PACKET_TYPE_ROOM,
+            ClientPackets::MyPosition { .. } => &PACKET_TYPE_MY_POSITION,
+            ClientPackets::MakeCorpse { .. } => &PACKET_TYPE_MAKE_CORPSE,
+            ClientPackets::ConnectionTest { .. } => &PACKET_TYPE_TEST,
+            ClientPackets::LogDebugMessage { .. } => &PACKET_TYPE_DEBUG_LOG,
+            ClientPackets::Bye => &PACKET_TYPE_BYE,
+            ClientPackets::HitNonPlayerUnit { .. } => &PACKET_TYPE_HIT_NON_PLAYER_UNIT,
+            ClientPackets::Heaven { .. } => &PACKET_TYPE_HEAVEN,
+            ClientPackets::ChangeColor { .. } => &PACKET_TYPE_CHANGE_COLOR,
+            ClientPackets::DrawOnField { .. } => &PACKET_TYPE_DRAW_ON_FIELD,
+            ClientPackets::Capabilities { .. } => &PACKET_TYPE_CAPABILITIES,
+        };
+        //all of the packet types are ASCII
+        std::str::from_utf8(packet_type).unwrap()
+    }
+    //the oldest protocol version that has this packet
+    pub fn protocol_version(&self) -> u16 {
+        match self {
+            ClientPackets::DrawOnField { .. } => DRAWING_PROTOCOL_VERSION,
+            _ => OLDEST_PROTOCOL_VERSION
+        }
+    }
+}
+
+pub const PACKET_TYPE_PROTOCOL            : [u8; 4] = *b"Prtc";
+pub const PACKET_TYPE_MAP_ATTRIBUTES      : [u8; 4] = *b"mAtt";
+pub const PACKET_TYPE_ROOM                : [u8; 4] = *b"Room";
+pub const PACKET_TYPE_MY_POSITION         : [u8; 4] = *b"myPo";
+pub const PACKET_TYPE_MAKE_CORPSE         : [u8; 4] = *b"mCrp";
+pub const PACKET_TYPE_TEST                : [u8; 4] = *b"Test";
+pub const PACKET_TYPE_DEBUG_LOG           : [u8; 4] = *b"Dlog";
+pub const PACKET_TYPE_BYE                 : [u8; 4] = *b"Bye.";
+pub const PACKET_TYPE_HIT_NON_PLAYER_UNIT : [u8; 4] = *b"HNPU";
+pub const PACKET_TYPE_HEAVEN              : [u8; 4] = *b"HVen";
+pub const PACKET_TYPE_CHANGE_COLOR        : [u8; 4] = *b"ChCl";
+pub const PACKET_TYPE_DRAW_ON_FIELD       : [u8; 4] = *b"DrFl";
+pub const PACKET_TYPE_CAPABILITIES        : [u8; 4] = *b"Caps";
+
+bitflags! {
+    //extensions to the protocol that the stock client doesn't know about
+    //a client that wants any of them sends Caps right after Prtc, and the server answers with a Caps of its own
+    //saying which ones it turned on for that connection. no Caps, no extensions
+    #[derive(Clone, Copy, Debug)]
+    pub struct Capabilities : u32 {
+        //the server can send Mesg after Flds
+        const ServerMessages = 1;
+    }
+}
+
+
+pub const PROTOCOL_BUFFER_SIZE : usize = 8;
+//what the final release of the stock client expects
+pub const PROTOCOL_NAME : &[u8; PROTOCOL_BUFFER_SIZE] = b"Soaprun\0";
+pub const PROTOCOL_VERSION : u16 = 64;
+pub const CLIENT_MAX_PLAYERS : usize = 63;
+pub const CLIENT_MAX_ENTITIES : usize = 64;
+
+//every version of the client since v0.390 (see docs/history.md)
+//UNVERIFIED: the packet layouts are assumed to be the same all the way back, only what's allowed in them changes
+//nobody has checked Prtc/Test/Flds (or whether the weather byte and item bits exist) against the older binaries yet
+pub const OLDEST_PROTOCOL_VERSION : u16 = 50;
+//v0.410
+pub const DRAWING_PROTOCOL_VERSION : u16 = 60;
+//v0.420
+pub const CROWN_PROTOCOL_VERSION : u16 = 61;
+//v0.425, which is presumably when shields came in too
+pub const WEATHER_PROTOCOL_VERSION : u16 = 62;
+//v0.430
+pub const CROSS_PROTOCOL_VERSION : u16 = 63;
+
+//the protocol version each release of the client expects back from Prtc
+pub fn protocol_version_for(game_version: u16) -> Option<u16> {
+    Some(match game_version {
+        390..=391 => 50,
+        392 => 52,
+        393 => 53,
+        410..=411 => 60,
+        420 => 61,
+        424..=425 => 62,
+        430..=431 => 63,
+        432..=433 => PROTOCOL_VERSION,
+        _ => return None
+    })
+}
+//the oldest protocol version that knows about this type of unit
+//UNVERIFIED: unit types were added in order, so the ones in between Chase and Cross are assumed to be from v0.425
+pub fn unit_protocol_version(unit_type: UnitTypes) -> u16 {
+    match unit_type {
+        UnitTypes::Goal | UnitTypes::Closer | UnitTypes::Sword | UnitTypes::Crawl
+        | UnitTypes::Hummer | UnitTypes::Rounder => OLDEST_PROTOCOL_VERSION,
+        UnitTypes::Wuss | UnitTypes::Chase | UnitTypes::Gate | UnitTypes::Shield => WEATHER_PROTOCOL_VERSION,
+        UnitTypes::Cross | UnitTypes::Snail => CROSS_PROTOCOL_VERSION,
+    }
+}
+//the items this protocol version knows about
+pub fn protocol_items(version: u16) -> SoaprunnerItems {
+    let mut items = SoaprunnerItems::Sword;
+    items.set(SoaprunnerItems::Crown, CROWN_PROTOCOL_VERSION <= version);
+    items.set(SoaprunnerItems::Shield, WEATHER_PROTOCOL_VERSION <= version);
+    items
+}
+
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug)]
+pub enum Weather {
+    Clear = 0,
+    Rainy
+}
+impl TryFrom<u8> for Weather {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Weather::Clear,
+            1 => Weather::Rainy,
+            _ => return Err(value)
+        })
+    }
+}
+#[derive(Debug)]
+pub struct ChangedTile {
+    pub x: i16,
+    pub y: i16,
+    pub tile: u8,
+    padding: u8
+}
+impl ChangedTile {
+    pub fn new(x: i16, y: i16, tile: u8) -> ChangedTile {
+        ChangedTile {
+            x, y, tile,
+            padding: 0
+        }
+    }
+}
+pub enum ServerPackets<'a> {
+    Welcome,
+    Protocol {
+        protocol: [u8; PROTOCOL_BUFFER_SIZE],
+        version: u16
+    },
+    MapAttributesResponse {
+        map_attributes: &'a MapAttributes
+    },
+    RoomResponse {
+        coords: RoomCoordinates,
+        room: &'a Room,
+    },
+    Fields {
+        client_state: SoaprunnerSprites,
+        client_color: SoaprunnerColors,
+        client_items: SoaprunnerItems,
+        //soaprunners_length: u8,
+        //entities_length: u8,
+        //tiles_length: u8,
+        weather: Weather,
+        soaprunners: Vec<(usize, Soaprunner)>,
+        entities: Vec<(usize, Unit)>,
+        tiles: Vec<ChangedTile>
+    },
+    ConnectionTest {
+        data: [u8; CONNECTION_TEST_DATA_SIZE]
+    },
+    Void,
+    Over,
+    //only ever sent to a client that sent Caps first
+    Capabilities {
+        capabilities: Capabilities
+    },
+    //only for Capabilities::ServerMessages
+    Message {
+        message: &'a str
+    },
+}
+pub const PACKET_TYPE_WELCOME : [u8; 4] = *b"WLCM";
+//sent instead of WLCM when the server is full, but the final client doesn't check for it
+pub const PACKET_TYPE_OVER    : [u8; 4] = *b"OVER";
+pub const PACKET_TYPE_FIELDS  : [u8; 4] = *b"Flds";
+pub const PACKET_TYPE_VOID    : [u8; 4] = *b"Void";
+pub const PACKET_TYPE_MESSAGE : [u8; 4] = *b"Mesg";
+//so a message always fits in a Flds-sized packet with plenty of room to spare
+pub const MAX_MESSAGE_LENGTH  : usize = 1024;
+
+impl ServerPackets<'_> {
+    //takes out anything an older client wouldn't know what to do with
+    pub fn for_protocol_version(self, version: u16) -> Self {
+        match self {
+            ServerPackets::Fields { client_state, client_color, client_items, weather, soaprunners, entities, tiles } => {
+                let items = protocol_items(version);
+                ServerPackets::Fields {
+                    client_state,
+                    client_color,
+                    client_items: client_items & items,
+                    weather: if version < WEATHER_PROTOCOL_VERSION { Weather::Clear } else { weather },
+                    soaprunners: soaprunners.into_iter().map(|(i, mut s)| {
+                        s.items &= items;
+                        (i, s)
+                    }).collect(),
+                    entities: entities.into_iter().filter(|(_, e)| unit_protocol_version(e.unit_type) <= version).collect(),
+                    tiles
+                }
+            },
+            packet => packet
+        }
+    }
+    //the capability a client needs to have negotiated to get this packet, if any
+    pub fn capability(&self) -> Option<Capabilities> {
+        match self {
+            ServerPackets::Message { .. } => Some(Capabilities::ServerMessages),
+            _ => None
+        }
+    }
+}
+
+//TODO this may be better off being a config option
+//since TECHNICALLY the largest valid packet is a debug message with u32::MAX(ish) characters
+//but the stock client will never send debug messages that long
+//so the most practical maximum is either
+//- the connection test (508 bytes)
+//- a draw request with the maximum movements (1026 bytes)
+//I'm not sure how bad your internet would have to be for the latter to be sent though
+//or if the client can even queue up 256 movement nodes to be sent
+//so more research is necessary
+pub const MAX_PACKET_LENGTH : usize = 5 + MAX_MOVEMENTS_LENGTH;
+pub const MIN_PACKET_LENGTH : usize = 4;
+//the same thing for packets going the other way, which is a completely full Flds
+//map attributes could technically be bigger, but only the first 256 are ever used
+pub const MAX_SERVER_PACKET_LENGTH : usize = 11
+    + (CLIENT_MAX_PLAYERS + CLIENT_MAX_ENTITIES) * (5 + MAX_MOVEMENTS_LENGTH)
+    + (u8::MAX as usize * 6);
+#[derive(Error, Debug)]
+pub enum ReadPacketErrors
+{
+    #[error("Invalid packet length: {length}")]
+    InvalidLengthError {
+        length: u32
+    },
+    #[error("Invalid packet type: {:?}", chars)]
+    InvalidTypeError {
+        chars: Vec<u8>
+    },
+    #[error("IO error while receiving packet: `{0}`", )]
+    IOError(#[from] std::io::Error),
+    #[error("Unexpected data amount: got {got} bytes when {expected} were expected")]
+    UnexpectedDataAmount {
+        got: usize,
+        expected: usize
+    },
+    #[error("The provided data was invalid for type {packet_type}. {:?}", data)]
+    InvalidDataError {
+        packet_type: String,
+        data: Vec<u8>
+    }
+}
+const MAX_MOVEMENTS_LENGTH : usize = 1 + (u8::MAX as usize * 4);
+impl Encode for ChangedTile {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.x.encode(data)?;
+        self.y.encode(data)?;
+        self.tile.encode(data)?;
+        self.padding.encode(data)
+    }
+}
+impl Decode for ChangedTile {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(ChangedTile {
+            x: i16::decode(data)?,
+            y: i16::decode(data)?,
+            tile: u8::decode(data)?,
+            padding: u8::decode(data)?
+        })
+    }
+}
+
+//the packet type, then the body
+impl Encode for ClientPackets {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        data.extend_from_slice(self.packet_type().as_bytes());
+        match self {
+            ClientPackets::MapAttributeRequest | ClientPackets::Bye => Ok(()),
+            ClientPackets::ProtocolRequest { game_version } => game_version.encode(data),
+            ClientPackets::RoomRequest { coords } => coords.encode(data),
+            ClientPackets::MyPosition { movements } | ClientPackets::Heaven { movements } => movements.encode(data),
+            ClientPackets::MakeCorpse { position } => position.encode(data),
+            ClientPackets::ConnectionTest { data: test_data } => test_data.encode(data),
+            ClientPackets::LogDebugMessage { message } => {
+                u32::try_from(message.len()).map_err(|_| too_big())?.encode(data)?;
+                data.extend_from_slice(message.as_bytes());
+                Ok(())
+            },
+            ClientPackets::HitNonPlayerUnit { index, movements } => {
+                index.encode(data)?;
+                movements.encode(data)
+            },
+            ClientPackets::ChangeColor { color, movements } => {
+                color.encode(data)?;
+                movements.encode(data)
+            },
+            ClientPackets::DrawOnField { position, tile, movements } => {
+                position.encode(data)?;
+                tile.encode(data)?;
+                movements.encode(data)
+            },
+            ClientPackets::Capabilities { capabilities } => capabilities.encode(data),
+        }
+    }
+}
+impl Decode for ClientPackets {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(match <[u8; 4]>::decode(data)? {
+            PACKET_TYPE_PROTOCOL => ClientPackets::ProtocolRequest { game_version: u16::decode(data)? },
+            PACKET_TYPE_TEST => ClientPackets::ConnectionTest { data: Decode::decode(data)? },
+            PACKET_TYPE_DEBUG_LOG => {
+                let length = u32::decode(data)? as usize;
+                let message = std::str::from_utf8(take(data, length)?).map_err(|_| DecodeError::InvalidValue)?;
+                ClientPackets::LogDebugMessage { message: message.to_owned() }
+            },
+            PACKET_TYPE_MAP_ATTRIBUTES => ClientPackets::MapAttributeRequest,
+            PACKET_TYPE_ROOM => ClientPackets::RoomRequest { coords: RoomCoordinates::decode(data)? },
+            PACKET_TYPE_CHANGE_COLOR => ClientPackets::ChangeColor {
+                color: u8::decode(data)?,
+                movements: Vec::decode(data)?
+            },
+            PACKET_TYPE_MY_POSITION => ClientPackets::MyPosition { movements: Vec::decode(data)? },
+            PACKET_TYPE_DRAW_ON_FIELD => ClientPackets::DrawOnField {
+                position: Position::decode(data)?,
+                tile: u8::decode(data)?,
+                movements: Vec::decode(data)?
+            },
+            PACKET_TYPE_HIT_NON_PLAYER_UNIT => ClientPackets::HitNonPlayerUnit {
+                index: u8::decode(data)?,
+                movements: Vec::decode(data)?
+            },
+            PACKET_TYPE_MAKE_CORPSE => ClientPackets::MakeCorpse { position: Position::decode(data)? },
+            PACKET_TYPE_HEAVEN => ClientPackets::Heaven { movements: Vec::decode(data)? },
+            PACKET_TYPE_BYE => ClientPackets::Bye,
+            PACKET_TYPE_CAPABILITIES => ClientPackets::Capabilities { capabilities: Capabilities::decode(data)? },
+            packet_type => return Err(DecodeError::UnknownType(packet_type))
+        })
+    }
+}
+
+impl Encode for ServerPackets<'_> {
+    fn encode(&self, data: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            ServerPackets::Welcome => PACKET_TYPE_WELCOME.encode(data),
+            ServerPackets::Void => PACKET_TYPE_VOID.encode(data),
+            ServerPackets::Over => PACKET_TYPE_OVER.encode(data),
+            ServerPackets::Capabilities { capabilities } => {
+                PACKET_TYPE_CAPABILITIES.encode(data)?;
+                capabilities.encode(data)
+            },
+            ServerPackets::Message { message } => {
+                if message.len() > MAX_MESSAGE_LENGTH {
+                    return Err(too_big())
+                }
+                PACKET_TYPE_MESSAGE.encode(data)?;
+                (message.len() as u32).encode(data)?;
+                data.extend_from_slice(message.as_bytes());
+                Ok(())
+            },
+            ServerPackets::Protocol { protocol, version } => {
+                PACKET_TYPE_PROTOCOL.encode(data)?;
+                protocol.encode(data)?;
+                version.encode(data)
+            },
+            ServerPackets::ConnectionTest { data: test_data } => {
+                PACKET_TYPE_TEST.encode(data)?;
+                test_data.encode(data)
+            },
+            ServerPackets::MapAttributesResponse { map_attributes } => {
+                PACKET_TYPE_MAP_ATTRIBUTES.encode(data)?;
+                map_attributes.encode(data)
+            },
+            ServerPackets::RoomResponse { coords, room } => {
+                PACKET_TYPE_ROOM.encode(data)?;
+                coords.encode(data)?;
+                room.encode(data)
+            },
+            ServerPackets::Fields {
+                client_state,
+                client_color,
+                client_items,
+                weather,
+                soaprunners,
+                entities,
+                tiles } => {
+                if soaprunners.len() > CLIENT_MAX_PLAYERS || entities.len() > CLIENT_MAX_ENTITIES {
+                    //the client only has room for so many
+                    return Err(too_big())
+                }
+                PACKET_TYPE_FIELDS.encode(data)?;
+                client_state.encode(data)?;
+                client_color.encode(data)?;
+                client_items.encode(data)?;
+
+                (soaprunners.len() as u8).encode(data)?;
+                (entities.len() as u8).encode(data)?;
+                u8::try_from(tiles.len()).map_err(|_| too_big())?.encode(data)?;
+                weather.encode(data)?;
+
+                for (i, s) in soaprunners {
+                    (*i as u8).encode(data)?;
+                    s.encode(data)?;
+                }
+                for (i, e) in entities {
+                    (*i as u8).encode(data)?;
+                    e.encode(data)?;
+                }
+                for ct in tiles {
+                    ct.encode(data)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+//decodes a whole packet, which has to use up every byte
+fn decode_packet<P : Decode>(packet: &[u8]) -> Result<P, ReadPacketErrors> {
+    if packet.len() < MIN_PACKET_LENGTH {
+        return Err(ReadPacketErrors::InvalidLengthError { length: packet.len() as u32 })
+    }
+    //lengths in errors are for the body, same as the docs
+    let (packet_type, body) = packet.split_at(MIN_PACKET_LENGTH);
+    let mut data = packet;
+    match P::decode(&mut data) {
+        Ok(p) if data.is_empty() => Ok(p),
+        Ok(_) => Err(ReadPacketErrors::UnexpectedDataAmount { got: body.len(), expected: body.len() - data.len() }),
+        Err(DecodeError::MissingData(n)) => Err(ReadPacketErrors::UnexpectedDataAmount { got: body.len(), expected: body.len() + n }),
+        Err(DecodeError::InvalidValue) => Err(ReadPacketErrors::InvalidDataError {
+            packet_type: String::from_utf8_lossy(packet_type).into_owned(),
+            data: body.to_vec()
+        }),
+        Err(DecodeError::UnknownType(chars)) => Err(ReadPacketErrors::InvalidTypeError { chars: Vec::from(chars) }),
+    }
+}
+fn send_packet<P : Encode + ?Sized>(stream: &mut (impl PacketStream + ?Sized), packet: &P) -> Result<(), Error> {
+    let mut data = Vec::new();
+    packet.encode(&mut data)?;
+    stream.write_packet(data)
+}
+
+pub fn read_packet(stream: &mut (impl PacketStream + ?Sized)) -> Result<ClientPackets, ReadPacketErrors>
+{
+    decode_packet(&stream.read_packet()?)
+}
+
+pub fn write_packet(stream: &mut (impl PacketStream + ?Sized), packet: ServerPackets) -> Result<(), Error> 
+{
+    send_packet(stream, &packet)
+}
+
+
+//everything the server can send, but owning what ServerPackets borrows
+//used by anything acting as a client
+pub enum ReceivedServerPackets {
+    Welcome,
+    Over,
+    Void,
+    Protocol {
+        protocol: [u8; PROTOCOL_BUFFER_SIZE],
+        version: u16
+    },
+    MapAttributesResponse {
+        map_attributes: MapAttributes
+    },
+    RoomResponse {
+        coords: RoomCoordinates,
+        room: Room
+    },
+    Fields {
+        client_state: SoaprunnerSprites,
+        client_color: SoaprunnerColors,
+        client_items: SoaprunnerItems,
+        weather: Weather,
+        soaprunners: Vec<(usize, Soaprunner)>,
+        entities: Vec<(usize, Unit)>,
+        tiles: Vec<ChangedTile>
+    },
+    ConnectionTest {
+        data: [u8; CONNECTION_TEST_DATA_SIZE]
+    },
+    Capabilities {
+        capabilities: Capabilities
+    },
+    Message {
+        message: String
+    },
+}
+impl ReceivedServerPackets {
+    pub fn packet_type(&self) -> &'static str {
+        let packet_type = match self {
+            ReceivedServerPackets::Welcome => &PACKET_TYPE_WELCOME,
+            ReceivedServerPackets::Over => &PACKET_TYPE_OVER,
+            ReceivedServerPackets::Void => &PACKET_TYPE_VOID,
+            ReceivedServerPackets::Protocol { .. } => &PACKET_TYPE_PROTOCOL,
+            ReceivedServerPackets::MapAttributesResponse { .. } => &PACKET_TYPE_MAP_ATTRIBUTES,
+            ReceivedServerPackets::RoomResponse { .. } => &PACKET_TYPE_ROOM,
+            ReceivedServerPackets::Fields { .. } => &PACKET_TYPE_FIELDS,
+            ReceivedServerPackets::ConnectionTest { .. } => &PACKET_TYPE_TEST,
+            ReceivedServerPackets::Capabilities { .. } => &PACKET_TYPE_CAPABILITIES,
+            ReceivedServerPackets::Message { .. } => &PACKET_TYPE_MESSAGE,
+        };
+        std::str::from_utf8(packet_type).unwrap()
+    }
+}
+
+impl Decode for ReceivedServerPackets {
+    fn decode(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(match <[u8; 4]>::decode(data)? {
+            PACKET_TYPE_WELCOME => ReceivedServerPackets::Welcome,
+            PACKET_TYPE_OVER => ReceivedServerPackets::Over,
+            PACKET_TYPE_VOID => ReceivedServerPackets::Void,
+            PACKET_TYPE_PROTOCOL => ReceivedServerPackets::Protocol {
+                protocol: Decode::decode(data)?,
+                version: u16::decode(data)?
+            },
+            PACKET_TYPE_TEST => ReceivedServerPackets::ConnectionTest { data: Decode::decode(data)? },
+            PACKET_TYPE_MAP_ATTRIBUTES => ReceivedServerPackets::MapAttributesResponse {
+                map_attributes: MapAttributes::decode(data)?
+            },
+            PACKET_TYPE_ROOM => ReceivedServerPackets::RoomResponse {
+                coords: RoomCoordinates::decode(data)?,
+                room: Room::decode(data)?
+            },
+            PACKET_TYPE_FIELDS => {
+                let client_state = SoaprunnerSprites::decode(data)?;
+                let client_color = SoaprunnerColors::decode(data)?;
+                let client_items = SoaprunnerItems::decode(data)?;
+                let soaprunner_count = u8::decode(data)?;
+                let entity_count = u8::decode(data)?;
+                let tile_count = u8::decode(data)?;
+                let weather = Weather::decode(data)?;
+                ReceivedServerPackets::Fields {
+                    client_state,
+                    client_color,
+                    client_items,
+                    weather,
+                    soaprunners: (0..soaprunner_count)
+                        .map(|_| Ok((u8::decode(data)? as usize, Soaprunner::decode(data)?)))
+                        .collect::<Result<_, _>>()?,
+                    entities: (0..entity_count)
+                        .map(|_| Ok((u8::decode(data)? as usize, Unit::decode(data)?)))
+                        .collect::<Result<_, _>>()?,
+                    tiles: (0..tile_count).map(|_| ChangedTile::decode(data)).collect::<Result<_, _>>()?
+                }
+            },
+            PACKET_TYPE_CAPABILITIES => ReceivedServerPackets::Capabilities { capabilities: Capabilities::decode(data)? },
+            PACKET_TYPE_MESSAGE => {
+                let length = u32::decode(data)? as usize;
+                let message = std::str::from_utf8(take(data, length)?).map_err(|_| DecodeError::InvalidValue)?;
+                ReceivedServerPackets::Message { message: message.to_owned() }
+            },
+            packet_type => return Err(DecodeError::UnknownType(packet_type))
+        })
+    }
+}
+
+pub fn read_server_packet(stream: &mut (impl PacketStream + ?Sized)) -> Result<ReceivedServerPackets, ReadPacketErrors>
+{
+    decode_packet(&stream.read_packet()?)
+}
+
+pub fn write_client_packet(stream: &mut (impl PacketStream + ?Sized), packet: &ClientPackets) -> Result<(), Error>
+{
+    send_packet(stream, packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::framing::PacketStream;
+    use crate::codec::Encode;
+    use crate::map_attributes::MapAttributes;
+    use crate::position::Position;
+    use crate::rooms::{Room, RoomCoordinates, CLIENT_ROOM_HEIGHT, CLIENT_ROOM_WIDTH};
+    use crate::soaprunners::{Soaprunner, SoaprunnerColors, SoaprunnerItems, SoaprunnerSprites};
+    use crate::units::{Unit, UnitStates, UnitTypes};
+    use super::{read_packet, read_server_packet, write_client_packet, write_packet, ChangedTile, ClientPackets, ReadPacketErrors,
+        ReceivedServerPackets, ServerPackets, Weather, Capabilities, CONNECTION_TEST_DATA_SIZE, MAX_MESSAGE_LENGTH, PROTOCOL_NAME, PROTOCOL_VERSION,
+        DRAWING_PROTOCOL_VERSION, OLDEST_PROTOCOL_VERSION, protocol_version_for};
+
+    //exactly what goes over TCP, length and all
+    fn encode(packet: ServerPackets) -> Vec<u8> {
+        //a loopback, so both ends are the same queue
+        let mut stream = VecDeque::new();
+        write_packet(&mut stream, packet).unwrap();
+        let packet = stream.read_packet().unwrap();
+        [&(packet.len() as u32).to_le_bytes()[..], &packet].concat()
+    }
+
+    //built by hand from the layouts in docs/protocol.md (which is what the stock client parses), not from captured traffic
+    #[test]
+    fn bodyless_packets_match_the_docs() {
+        assert_eq!(encode(ServerPackets::Welcome), b"\x04\x00\x00\x00WLCM");
+        assert_eq!(encode(ServerPackets::Over), b"\x04\x00\x00\x00OVER");
+        assert_eq!(encode(ServerPackets::Void), b"\x04\x00\x00\x00Void");
+    }
+
+    #[test]
+    fn protocol_matches_the_docs() {
+        let packet = encode(ServerPackets::Protocol { protocol: *PROTOCOL_NAME, version: PROTOCOL_VERSION });
+        assert_eq!(packet, b"\x0e\x00\x00\x00PrtcSoaprun\x00\x40\x00");
+    }
+
+    #[test]
+    fn connection_test_matches_the_docs() {
+        let data = [0x5a; CONNECTION_TEST_DATA_SIZE];
+        let packet = encode(ServerPackets::ConnectionTest { data });
+        assert_eq!(packet, [&b"\x00\x02\x00\x00Test"[..], &data].concat());
+    }
+
+    #[test]
+    fn map_attributes_match_the_docs() {
+        //the only real data in here, the response docs/protocol.md reports from when the original server was live
+        let row = [0, 1, 0, 3, 2, 0, 1, 3, 1, 1, 1, 0, 0, 2, 2, 2];
+        let map_attributes = MapAttributes { width: 16, height: 2, attributes: [row, row].concat() };
+        let packet = encode(ServerPackets::MapAttributesResponse { map_attributes: &map_attributes });
+        assert_eq!(packet, [&b"\x28\x00\x00\x00mAtt\x10\x00\x02\x00"[..], &row, &row].concat());
+    }
+
+    #[test]
+    fn room_matches_the_docs() {
+        let mut room = Room { data: [0; CLIENT_ROOM_WIDTH * CLIENT_ROOM_HEIGHT] };
+        for (i, tile) in room.data.iter_mut().enumerate() {
+            *tile = (i % 32) as u8;
+        }
+        //room coordinates are signed
+        let packet = encode(ServerPackets::RoomResponse { coords: RoomCoordinates { x: 1, y: -1 }, room: &room });
+        assert_eq!(packet, [&b"\x56\x01\x00\x00Room\x01\xff"[..], &room.data].concat());
+    }
+
+    #[test]
+    fn fields_match_the_docs() {
+        let packet = encode(ServerPackets::Fields {
+            client_state: SoaprunnerSprites::Walking,
+            client_color: SoaprunnerColors::Pink,
+            client_items: SoaprunnerItems::Sword,
+            weather: Weather::Rainy,
+            soaprunners: vec![(3, Soaprunner {
+                teleport_trigger: 2,
+                sprite: SoaprunnerSprites::Winning,
+                color: SoaprunnerColors::Blue,
+                items: SoaprunnerItems::Crown | SoaprunnerItems::Shield,
+                movements: vec![Position { x: 30, y: 22 }]
+            })],
+            entities: vec![(0, Unit {
+                teleport_trigger: 0,
+                unit_state: UnitStates::Active,
+                unit_type: UnitTypes::Sword,
+                direction: 0,
+                movements: vec![Position { x: 32, y: 22 }, Position { x: -33, y: 22 }]
+            })],
+            tiles: vec![ChangedTile::new(-5, 7, 13)]
+        });
+        let expected = [
+            &b"\x29\x00\x00\x00Flds"[..],
+            //walking, pink, sword, 1 soaprunner, 1 entity, 1 tile, rainy
+            b"\x01\x01\x01\x01\x01\x01\x01",
+            //soaprunner 3, teleport trigger 2, winning, blue, crown + shield, 1 movement
+            b"\x03\x02\x03\x02\x06\x01\x1e\x00\x16\x00",
+            //entity 0, teleport trigger 0, active, sword, direction 0, 2 movements
+            b"\x00\x00\x01\x02\x00\x02\x20\x00\x16\x00\xdf\xff\x16\x00",
+            //tile (-5,7) is now 13, then the padding byte
+            b"\xfb\xff\x07\x00\x0d\x00",
+        ].concat();
+        assert_eq!(packet, expected);
+    }
+
+    //not in the docs, since the stock client never sees them
+    #[test]
+    fn extensions_are_little_endian_too() {
+        let packet = encode(ServerPackets::Capabilities { capabilities: Capabilities::ServerMessages });
+        assert_eq!(packet, b"\x08\x00\x00\x00Caps\x01\x00\x00\x00");
+        let packet = encode(ServerPackets::Message { message: "hi" });
+        assert_eq!(packet, b"\x0a\x00\x00\x00Mesg\x02\x00\x00\x00hi");
+
+        let long = "a".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert!(write_packet(&mut VecDeque::new(), ServerPackets::Message { message: &long }).is_err());
+        //capabilities from some newer client are ignored
+        let mut stream = VecDeque::from([b"Caps\x03\x00\x00\x80".to_vec()]);
+        let Ok(ClientPackets::Capabilities { capabilities }) = read_packet(&mut stream) else {
+            panic!("expected Caps")
+        };
+        assert_eq!(capabilities.bits(), Capabilities::ServerMessages.bits());
+    }
+
+    #[test]
+    fn movement_lengths_are_checked() {
+        let mut stream = VecDeque::new();
+        //says one movement, but has two
+        stream.write_packet(b"myPo\x01\x1e\x00\x16\x00\x1e\x00\x16\x00".to_vec()).unwrap();
+        assert!(matches!(read_packet(&mut stream), Err(ReadPacketErrors::UnexpectedDataAmount { got: 9, expected: 5 })));
+        //says two movements, but has one
+        stream.write_packet(b"HNPU\x00\x02\x1e\x00\x16\x00".to_vec()).unwrap();
+        assert!(matches!(read_packet(&mut stream), Err(ReadPacketErrors::UnexpectedDataAmount { got: 6, expected: 10 })));
+        stream.write_packet(b"DrFl\x1e\x00\x16\x00\x0c\x01\x1e\x00\x16\x00\x00".to_vec()).unwrap();
+        assert!(matches!(read_packet(&mut stream), Err(ReadPacketErrors::UnexpectedDataAmount { got: 11, expected: 10 })));
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let mut stream = VecDeque::new();
+        let movements = vec![Position { x: 30, y: 22 }, Position { x: -1, y: 300 }];
+        let packets = [
+            ClientPackets::ProtocolRequest { game_version: 64 },
+            ClientPackets::LogDebugMessage { message: "héllo".to_owned() },
+            ClientPackets::RoomRequest { coords: RoomCoordinates { x: -2, y: 3 } },
+            ClientPackets::DrawOnField { position: Position { x: 31, y: 22 }, tile: 13, movements: movements.clone() },
+            ClientPackets::HitNonPlayerUnit { index: 4, movements },
+            ClientPackets::Bye,
+        ];
+        for packet in &packets {
+            write_client_packet(&mut stream, packet).unwrap();
+            let mut first = Vec::new();
+            let mut second = Vec::new();
+            packet.encode(&mut first).unwrap();
+            read_packet(&mut stream).unwrap().encode(&mut second).unwrap();
+            assert_eq!(first, second);
+        }
+
+        write_packet(&mut stream, ServerPackets::Fields {
+            client_state: SoaprunnerSprites::Walking,
+            client_color: SoaprunnerColors::Pink,
+            client_items: SoaprunnerItems::Sword,
+            weather: Weather::Rainy,
+            soaprunners: Vec::new(),
+            entities: Vec::new(),
+            tiles: vec![ChangedTile::new(-5, 7, 13)]
+        }).unwrap();
+        let Ok(ReceivedServerPackets::Fields { tiles, .. }) = read_server_packet(&mut stream) else {
+            panic!("expected Flds")
+        };
+        assert!(matches!(tiles[..], [ChangedTile { x: -5, y: 7, tile: 13, .. }]));
+
+        //an item that doesn't exist
+        stream.write_packet(b"Flds\x01\x01\x80\x00\x00\x00\x00".to_vec()).unwrap();
+        assert!(matches!(read_server_packet(&mut stream), Err(ReadPacketErrors::InvalidDataError { .. })));
+        stream.write_packet(b"Nope".to_vec()).unwrap();
+        assert!(matches!(read_packet(&mut stream), Err(ReadPacketErrors::InvalidTypeError { .. })));
+    }
+
+    #[test]
+    fn older_protocols_get_less() {
+        assert_eq!(protocol_version_for(392), Some(52));
+        assert_eq!(protocol_version_for(432), Some(PROTOCOL_VERSION));
+        assert_eq!(protocol_version_for(100), None);
+
+        let unit = |unit_type| Unit { teleport_trigger: 0, unit_state: UnitStates::Active, unit_type, direction: 0, movements: Vec::new() };
+        let fields = ServerPackets::Fields {
+            client_state: SoaprunnerSprites::Walking,
+            client_color: SoaprunnerColors::Green,
+            client_items: SoaprunnerItems::all(),
+            weather: Weather::Rainy,
+            soaprunners: vec![(1, Soaprunner {
+                teleport_trigger: 0,
+                sprite: SoaprunnerSprites::Walking,
+                color: SoaprunnerColors::Blue,
+                items: SoaprunnerItems::Crown | SoaprunnerItems::Shield,
+                movements: Vec::new()
+            })],
+            entities: vec![(0, unit(UnitTypes::Sword)), (1, unit(UnitTypes::Wuss)), (2, unit(UnitTypes::Cross))],
+            tiles: Vec::new()
+        };
+        //v0.420 has crowns, but no shields, weather, wusses or crosses
+        let ServerPackets::Fields { client_items, weather, soaprunners, entities, .. } = fields.for_protocol_version(61) else {
+            panic!("expected Flds")
+        };
+        assert_eq!(client_items.bits(), (SoaprunnerItems::Sword | SoaprunnerItems::Crown).bits());
+        assert_eq!(soaprunners[0].1.items.bits(), SoaprunnerItems::Crown.bits());
+        assert!(matches!(weather, Weather::Clear));
+        assert!(matches!(entities[..], [(0, Unit { unit_type: UnitTypes::Sword, .. })]));
+
+        assert_eq!(ClientPackets::Bye.protocol_version(), OLDEST_PROTOCOL_VERSION);
+        assert_eq!(ClientPackets::DrawOnField { position: Position { x: 0, y: 0 }, tile: 0, movements: Vec::new() }.protocol_version(), DRAWING_PROTOCOL_VERSION);
+    }
+}
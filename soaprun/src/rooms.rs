@@ -51,8 +51,9 @@ impl Position {
         let mut result = HashSet::with_capacity(4);
 
         //this method of determining the room is biased towards the north west...
-        let x = self.x / ((CLIENT_ROOM_WIDTH  - 1) as i16);
-        let y = self.y / ((CLIENT_ROOM_HEIGHT - 1) as i16);
+        //(rounding down, not towards 0, or negative positions end up in the room to their south east)
+        let x = self.x.div_euclid((CLIENT_ROOM_WIDTH  - 1) as i16);
+        let y = self.y.div_euclid((CLIENT_ROOM_HEIGHT - 1) as i16);
         let base = RoomCoordinates {
             x: x.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
             y: y.clamp(i8::MIN as i16, i8::MAX as i16) as i8
@@ -173,9 +174,9 @@ mod tests {
         assert!(r1.south_edge().eq(r2.north_edge()));
     }
 
-    use crate::soaprun::position::Position;
-    use crate::soaprun::rooms::{RoomCoordinates, MAX_X_COORD, MAX_Y_COORD, MIN_X_COORD, MIN_Y_COORD};
-    use crate::soaprun::soaprunners::{CLIENT_SPAWN_POSITION, CLIENT_SPAWN_ROOM};
+    use crate::position::Position;
+    use crate::rooms::{RoomCoordinates, MAX_X_COORD, MAX_Y_COORD, MIN_X_COORD, MIN_Y_COORD};
+    use crate::soaprunners::{CLIENT_SPAWN_POSITION, CLIENT_SPAWN_ROOM};
 
     #[test]
     fn get_affected_rooms_works() {
@@ -222,6 +223,23 @@ mod tests {
         assert_eq!(south_east_pos.get_affected_rooms(), HashSet::from([south_east_room]));
     }
 
+    //dividing towards 0 used to put these in the room to their south east, which doesn't contain them
+    #[test]
+    fn negative_positions_are_in_their_own_rooms() {
+        let negative_pos = Position {
+            x: -5,
+            y: -3
+        };
+        assert_eq!(negative_pos.get_affected_rooms(), HashSet::from([RoomCoordinates { x: -1, y: -1 }]));
+        //every room a position is in has to actually contain it
+        for x in -50..50 {
+            for y in -50..50 {
+                let p = Position { x, y };
+                assert!(p.get_affected_rooms().iter().all(|r| p.to_index(r).is_ok()), "{p} isn't in all of its rooms");
+            }
+        }
+    }
+
     fn test_to_index_room(rc: RoomCoordinates) {
         let x_offset = rc.x as isize * (CLIENT_ROOM_WIDTH - 1) as isize;
         let y_offset = rc.y as isize * (CLIENT_ROOM_HEIGHT - 1) as isize;
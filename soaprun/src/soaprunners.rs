@@ -12,6 +12,19 @@ pub enum SoaprunnerSprites {
     Winning,
     Ghost
 }
+impl TryFrom<u8> for SoaprunnerSprites {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => SoaprunnerSprites::Idle,
+            1 => SoaprunnerSprites::Walking,
+            2 => SoaprunnerSprites::Dying,
+            3 => SoaprunnerSprites::Winning,
+            4 => SoaprunnerSprites::Ghost,
+            _ => return Err(value)
+        })
+    }
+}
 #[repr(u8)]
 #[derive(Clone, Copy, Debug)]
 pub enum SoaprunnerColors {
@@ -20,6 +33,18 @@ pub enum SoaprunnerColors {
     Blue,
     Yellow
 }
+impl TryFrom<u8> for SoaprunnerColors {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => SoaprunnerColors::Green,
+            1 => SoaprunnerColors::Pink,
+            2 => SoaprunnerColors::Blue,
+            3 => SoaprunnerColors::Yellow,
+            _ => return Err(value)
+        })
+    }
+}
 
 bitflags! {
     #[derive(Clone, Copy, Debug)]
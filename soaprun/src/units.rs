@@ -0,0 +1,75 @@
+use serde::Serialize;
+
+use super::position::Position;
+
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum UnitStates {
+    Sleeping = 0,
+    Active,
+    Corpse,
+    Flickering,
+    Gone
+}
+impl TryFrom<u8> for UnitStates {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => UnitStates::Sleeping,
+            1 => UnitStates::Active,
+            2 => UnitStates::Corpse,
+            3 => UnitStates::Flickering,
+            4 => UnitStates::Gone,
+            _ => return Err(value)
+        })
+    }
+}
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Serialize)]
+pub enum UnitTypes
+{
+    Goal = 0,
+    Closer,
+    Sword,
+    Crawl,
+    Hummer,
+    Rounder,
+    Wuss,
+    Chase,
+    Gate,
+    Shield,
+    Cross,
+    Snail
+}
+impl TryFrom<u8> for UnitTypes {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => UnitTypes::Goal,
+            1 => UnitTypes::Closer,
+            2 => UnitTypes::Sword,
+            3 => UnitTypes::Crawl,
+            4 => UnitTypes::Hummer,
+            5 => UnitTypes::Rounder,
+            6 => UnitTypes::Wuss,
+            7 => UnitTypes::Chase,
+            8 => UnitTypes::Gate,
+            9 => UnitTypes::Shield,
+            10 => UnitTypes::Cross,
+            11 => UnitTypes::Snail,
+            _ => return Err(value)
+        })
+    }
+}
+#[derive(Debug, Clone)]
+pub struct Unit {
+    //pub index: u8,
+    pub teleport_trigger: u8,
+    pub unit_state: UnitStates,
+    pub unit_type: UnitTypes,
+    pub direction: u8,
+    //pub movements_length: u8,
+    pub movements: Vec<Position>
+}
\ No newline at end of file
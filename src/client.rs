@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::server::{connect_stream, FramedStream, Transports, CONNECTION_TESTS, PROTOCOL_NAME, PROTOCOL_VERSION};
+use soaprun::map_attributes::MapAttributes;
+use soaprun::packets::*;
+use soaprun::position::Position;
+use soaprun::rooms::*;
+use soaprun::soaprunners::*;
+use soaprun::units::Unit;
+
+//v0.432, the last version of the game
+pub const CLIENT_GAME_VERSION : u16 = 432;
+//how likely a random walk keeps going the same way
+const KEEP_DIRECTION_CHANCE : f64 = 0.75;
+const DIRECTIONS : [fn(&Position, i16) -> Position; 4] = [Position::north, Position::south, Position::east, Position::west];
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Connection error: `{0}`")]
+    IOError(#[from] io::Error),
+    #[error("Invalid packet from the server: `{0}`")]
+    ReadPacketError(#[from] ReadPacketErrors),
+    #[error("The server is full")]
+    ServerFull,
+    #[error("Expected {expected} from the server, but got {got}")]
+    UnexpectedPacket {
+        expected: &'static str,
+        got: &'static str
+    },
+    #[error("The server is running {protocol:?} version {version}")]
+    WrongProtocol {
+        protocol: String,
+        version: u16
+    },
+    #[error("The server sent back different connection test data")]
+    ConnectionTestMismatch,
+    #[error("Can't walk from {from} to {to}")]
+    Blocked {
+        from: Position,
+        to: Position
+    }
+}
+fn unexpected(expected: &'static str, got: &ReceivedServerPackets) -> ClientError {
+    ClientError::UnexpectedPacket { expected, got: got.packet_type() }
+}
+
+pub struct SoaprunClient {
+    stream: Box<dyn FramedStream>,
+    pub map_attributes: MapAttributes,
+    pub rooms: HashMap<RoomCoordinates, Room>,
+    pub position: Position,
+    //where we've been since the last packet
+    movements: Vec<Position>,
+    pub state: SoaprunnerSprites,
+    pub color: SoaprunnerColors,
+    pub items: SoaprunnerItems,
+    pub weather: Weather,
+    //everyone else as of the last Flds, by index
+    pub soaprunners: BTreeMap<usize, Soaprunner>,
+    pub entities: BTreeMap<usize, Unit>,
+    has_made_corpse: bool,
+    //which way random_step is going
+    direction: fn(&Position, i16) -> Position
+}
+impl SoaprunClient {
+    //does everything the stock client does before the player can move
+    //WLCM -> Prtc -> Test x4 -> Dlog -> mAtt -> Room (the 3x3 around spawn) -> myPo
+    pub fn connect(address: &str, transport: Transports) -> Result<SoaprunClient, ClientError> {
+        SoaprunClient::handshake(connect_stream(address, transport)?)
+    }
+    //same as connect, but over a stream that's already open
+    pub fn handshake(mut stream: Box<dyn FramedStream>) -> Result<SoaprunClient, ClientError> {
+        match read_server_packet(stream.as_mut())? {
+            ReceivedServerPackets::Welcome => { },
+            ReceivedServerPackets::Over => return Err(ClientError::ServerFull),
+            p => return Err(unexpected("WLCM", &p)),
+        }
+
+        write_client_packet(stream.as_mut(), &ClientPackets::ProtocolRequest { game_version: CLIENT_GAME_VERSION })?;
+        match read_server_packet(stream.as_mut())? {
+            ReceivedServerPackets::Protocol { protocol, version } if protocol == *PROTOCOL_NAME && version == PROTOCOL_VERSION => { },
+            ReceivedServerPackets::Protocol { protocol, version } => return Err(ClientError::WrongProtocol {
+                protocol: String::from_utf8_lossy(&protocol).trim_end_matches('\0').to_owned(),
+                version
+            }),
+            p => return Err(unexpected("Prtc", &p)),
+        }
+
+        for i in 0..CONNECTION_TESTS {
+            let test_data = [i; CONNECTION_TEST_DATA_SIZE];
+            write_client_packet(stream.as_mut(), &ClientPackets::ConnectionTest { data: test_data })?;
+            match read_server_packet(stream.as_mut())? {
+                ReceivedServerPackets::ConnectionTest { data } if data == test_data => { },
+                ReceivedServerPackets::ConnectionTest { .. } => return Err(ClientError::ConnectionTestMismatch),
+                p => return Err(unexpected("Test", &p)),
+            }
+        }
+
+        write_client_packet(stream.as_mut(), &ClientPackets::LogDebugMessage {
+            message: format!("soapdispenser bot {}", env!("CARGO_PKG_VERSION"))
+        })?;
+        match read_server_packet(stream.as_mut())? {
+            ReceivedServerPackets::Void => { },
+            p => return Err(unexpected("Void", &p)),
+        }
+
+        write_client_packet(stream.as_mut(), &ClientPackets::MapAttributeRequest)?;
+        let map_attributes = match read_server_packet(stream.as_mut())? {
+            ReceivedServerPackets::MapAttributesResponse { map_attributes } => map_attributes,
+            p => return Err(unexpected("mAtt", &p)),
+        };
+
+        let mut client = SoaprunClient {
+            stream,
+            map_attributes,
+            rooms: HashMap::new(),
+            position: CLIENT_SPAWN_POSITION,
+            movements: Vec::new(),
+            state: SoaprunnerSprites::Walking,
+            color: SoaprunnerColors::Green,
+            items: SoaprunnerItems::empty(),
+            weather: Weather::Clear,
+            soaprunners: BTreeMap::new(),
+            entities: BTreeMap::new(),
+            has_made_corpse: false,
+            direction: DIRECTIONS[0]
+        };
+        client.load_rooms_around(CLIENT_SPAWN_POSITION)?;
+        //the first movement has to end at spawn
+        client.movements.push(CLIENT_SPAWN_POSITION);
+        client.send_position()?;
+        Ok(client)
+    }
+
+    pub fn request_room(&mut self, coords: RoomCoordinates) -> Result<(), ClientError> {
+        write_client_packet(self.stream.as_mut(), &ClientPackets::RoomRequest { coords })?;
+        match read_server_packet(self.stream.as_mut())? {
+            ReceivedServerPackets::RoomResponse { coords, room } => {
+                self.rooms.insert(coords, room);
+                Ok(())
+            },
+            p => Err(unexpected("Room", &p)),
+        }
+    }
+    //like the stock client, keep every room next to us loaded so we know where we can go
+    fn load_rooms_around(&mut self, pos: Position) -> Result<(), ClientError> {
+        for room in pos.get_affected_rooms() {
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (0, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let coords = RoomCoordinates { x: room.x.saturating_add(dx), y: room.y.saturating_add(dy) };
+                if !self.rooms.contains_key(&coords) {
+                    self.request_room(coords)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tile_type(&self, pos: &Position, room: &RoomCoordinates) -> Option<u8> {
+        let tile = self.rooms.get(room)?.data[pos.to_index(room).ok()?];
+        self.map_attributes.attributes.get(tile as usize).copied()
+    }
+    //the same rules as the server's verify_nodes, but for a single tile
+    pub fn can_step(&self, from: &Position, to: &Position) -> bool {
+        if from.taxicab_distance(to) != 1
+        || to.x < MIN_X_COORD || MAX_X_COORD < to.x
+        || to.y < MIN_Y_COORD || MAX_Y_COORD < to.y
+        || (from.on_edge() && to.on_edge()) {
+            return false
+        }
+        if matches!(self.state, SoaprunnerSprites::Ghost) || from.on_edge() {
+            return true
+        }
+        to.get_affected_rooms().iter().all(|r| match self.tile_type(to, r) {
+            Some(0) => true,
+            Some(2) => !self.items.contains(SoaprunnerItems::Shield),
+            _ => false
+        })
+    }
+    //moves one tile, which gets sent along with the next packet
+    pub fn step(&mut self, to: Position) -> Result<(), ClientError> {
+        if !self.can_step(&self.position, &to) {
+            return Err(ClientError::Blocked { from: self.position, to })
+        }
+        self.position = to;
+        self.movements.push(to);
+        self.load_rooms_around(to)
+    }
+    //idle, walking, or a ghost
+    pub fn is_playing(&self) -> bool {
+        !matches!(self.state, SoaprunnerSprites::Dying | SoaprunnerSprites::Winning)
+    }
+
+    fn send_and_read_fields(&mut self, packet: ClientPackets) -> Result<(), ClientError> {
+        write_client_packet(self.stream.as_mut(), &packet)?;
+        match read_server_packet(self.stream.as_mut())? {
+            ReceivedServerPackets::Fields { client_state, client_color, client_items, weather, soaprunners, entities, tiles } => {
+                self.state = client_state;
+                self.color = client_color;
+                self.items = client_items;
+                self.weather = weather;
+                self.soaprunners = BTreeMap::from_iter(soaprunners);
+                self.entities = BTreeMap::from_iter(entities);
+                for tile in tiles {
+                    let pos = Position { x: tile.x, y: tile.y };
+                    for r in pos.get_affected_rooms() {
+                        if let (Some(room), Ok(i)) = (self.rooms.get_mut(&r), pos.to_index(&r)) {
+                            room.data[i] = tile.tile;
+                        }
+                    }
+                }
+                Ok(())
+            },
+            p => Err(unexpected("Flds", &p)),
+        }
+    }
+    pub fn send_position(&mut self) -> Result<(), ClientError> {
+        let movements = std::mem::take(&mut self.movements);
+        self.send_and_read_fields(ClientPackets::MyPosition { movements })
+    }
+    pub fn hit(&mut self, index: u8) -> Result<(), ClientError> {
+        let movements = std::mem::take(&mut self.movements);
+        self.send_and_read_fields(ClientPackets::HitNonPlayerUnit { index, movements })
+    }
+    pub fn draw(&mut self, position: Position, tile: u8) -> Result<(), ClientError> {
+        let movements = std::mem::take(&mut self.movements);
+        self.send_and_read_fields(ClientPackets::DrawOnField { position, tile, movements })
+    }
+    fn send_and_read_void(&mut self, packet: ClientPackets) -> Result<(), ClientError> {
+        write_client_packet(self.stream.as_mut(), &packet)?;
+        match read_server_packet(self.stream.as_mut())? {
+            ReceivedServerPackets::Void => Ok(()),
+            p => Err(unexpected("Void", &p)),
+        }
+    }
+    //leaves a corpse behind first if we died, like the stock client
+    pub fn bye(mut self) -> Result<(), ClientError> {
+        if matches!(self.state, SoaprunnerSprites::Dying) && !self.has_made_corpse {
+            self.has_made_corpse = true;
+            self.send_and_read_void(ClientPackets::MakeCorpse { position: self.position })?;
+        }
+        self.send_and_read_void(ClientPackets::Bye)
+    }
+
+    //one tile per interval, sending our position every time
+    pub fn run_script(&mut self, script: &[ScriptCommands], interval: Duration) -> Result<(), ClientError> {
+        for command in script {
+            if !self.is_playing() {
+                break
+            }
+            match command {
+                ScriptCommands::Walk { direction, tiles } => {
+                    for _ in 0..*tiles {
+                        self.step(direction(&self.position, 1))?;
+                        self.send_position()?;
+                        thread::sleep(interval);
+                    }
+                },
+                ScriptCommands::Wait(duration) => {
+                    //the server wants to hear from us, even if we're not moving
+                    let mut waited = Duration::ZERO;
+                    while waited < *duration {
+                        self.send_position()?;
+                        thread::sleep(interval);
+                        waited += interval;
+                    }
+                },
+                ScriptCommands::Hit(index) => self.hit(*index)?,
+                ScriptCommands::Draw(tile) => self.draw(self.position, *tile)?,
+                ScriptCommands::Room(coords) => self.request_room(*coords)?,
+            }
+        }
+        Ok(())
+    }
+    //usually keeps going the same way, unless it's blocked
+    pub fn random_step<R : Rng>(&mut self, rng: &mut R) -> Result<(), ClientError> {
+        let keep_going = rng.gen_bool(KEEP_DIRECTION_CHANCE) && self.can_step(&self.position, &(self.direction)(&self.position, 1));
+        if !keep_going {
+            let options = Vec::from_iter(DIRECTIONS.into_iter().filter(|d| self.can_step(&self.position, &d(&self.position, 1))));
+            match options.choose(rng) {
+                Some(d) => self.direction = *d,
+                //boxed in, so just stand there
+                None => return Ok(()),
+            }
+        }
+        self.step((self.direction)(&self.position, 1))
+    }
+    //walks around at random until the game ends or it's taken that many steps
+    pub fn random_walk<R : Rng>(&mut self, steps: Option<usize>, interval: Duration, rng: &mut R) -> Result<(), ClientError> {
+        let mut taken = 0;
+        while self.is_playing() && steps.is_none_or(|s| taken < s) {
+            self.random_step(rng)?;
+            self.send_position()?;
+            taken += 1;
+            thread::sleep(interval);
+        }
+        Ok(())
+    }
+}
+
+pub enum ScriptCommands {
+    Walk {
+        direction: fn(&Position, i16) -> Position,
+        tiles: usize
+    },
+    Wait(Duration),
+    Hit(u8),
+    Draw(u8),
+    Room(RoomCoordinates)
+}
+
+#[derive(Error, Debug)]
+#[error("Line {line} of the script is invalid: \"{text}\"")]
+pub struct ParseScriptError {
+    pub line: usize,
+    pub text: String
+}
+
+//one command per line, and anything after a # is ignored:
+//  walk <north|south|east|west> <tiles>
+//  wait <milliseconds>
+//  hit <entity>
+//  draw <tile>
+//  room <x> <y>
+pub fn parse_script(script: &str) -> Result<Vec<ScriptCommands>, ParseScriptError> {
+    let mut commands = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let text = line.split('#').next().unwrap_or_default();
+        let args = Vec::from_iter(text.split_whitespace());
+        let command = match args.as_slice() {
+            [] => continue,
+            ["walk", direction, tiles] => {
+                let direction: Option<fn(&Position, i16) -> Position> = match *direction {
+                    "north" => Some(Position::north),
+                    "south" => Some(Position::south),
+                    "east" => Some(Position::east),
+                    "west" => Some(Position::west),
+                    _ => None
+                };
+                direction.zip(tiles.parse().ok()).map(|(direction, tiles)| ScriptCommands::Walk { direction, tiles })
+            },
+            ["wait", ms] => ms.parse().ok().map(|ms| ScriptCommands::Wait(Duration::from_millis(ms))),
+            ["hit", index] => index.parse().ok().map(ScriptCommands::Hit),
+            ["draw", tile] => tile.parse().ok().map(ScriptCommands::Draw),
+            ["room", x, y] => match (x.parse(), y.parse()) {
+                (Ok(x), Ok(y)) => Some(ScriptCommands::Room(RoomCoordinates { x, y })),
+                _ => None
+            },
+            _ => None
+        };
+        match command {
+            Some(c) => commands.push(c),
+            None => return Err(ParseScriptError { line: i + 1, text: line.to_owned() }),
+        }
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::MemoryStream;
+    use soaprun::packets::*;
+    use soaprun::position::Position;
+    use soaprun::soaprunners::*;
+    use soaprun::units::*;
+
+    use super::{parse_script, ScriptCommands};
+
+    #[test]
+    fn client_packets_round_trip() {
+        let (mut client, mut server) = MemoryStream::pair();
+        let movements = vec![Position { x: 30, y: 22 }, Position { x: 31, y: 22 }];
+        write_client_packet(&mut client, &ClientPackets::DrawOnField { position: Position { x: 31, y: 22 }, tile: 13, movements: movements.clone() }).unwrap();
+        match read_packet(&mut server) {
+            Ok(ClientPackets::DrawOnField { position, tile, movements: got }) => {
+                assert_eq!(position, Position { x: 31, y: 22 });
+                assert_eq!(tile, 13);
+                assert_eq!(got, movements);
+            },
+            _ => panic!("Expected DrFl"),
+        }
+
+        write_packet(&mut server, ServerPackets::Fields {
+            client_state: SoaprunnerSprites::Walking,
+            client_color: SoaprunnerColors::Blue,
+            client_items: SoaprunnerItems::Sword,
+            weather: Weather::Rainy,
+            soaprunners: vec![(3, Soaprunner { teleport_trigger: 1, sprite: SoaprunnerSprites::Ghost, color: SoaprunnerColors::Pink, items: SoaprunnerItems::empty(), movements: movements.clone() })],
+            entities: vec![(0, Unit { teleport_trigger: 0, unit_state: UnitStates::Active, unit_type: UnitTypes::Closer, direction: 0, movements: vec![] })],
+            tiles: vec![ChangedTile::new(-5, 7, 13)]
+        }).unwrap();
+        match read_server_packet(&mut client) {
+            Ok(ReceivedServerPackets::Fields { client_color, weather, soaprunners, entities, tiles, .. }) => {
+                assert!(matches!(client_color, SoaprunnerColors::Blue));
+                assert!(matches!(weather, Weather::Rainy));
+                assert_eq!(soaprunners[0].0, 3);
+                assert_eq!(soaprunners[0].1.movements, movements);
+                assert!(matches!(entities[0].1.unit_type, UnitTypes::Closer));
+                assert_eq!((tiles[0].x, tiles[0].y, tiles[0].tile), (-5, 7, 13));
+            },
+            _ => panic!("Expected Flds"),
+        }
+    }
+
+    #[test]
+    fn scripts_parse() {
+        let script = parse_script("walk east 3 # to the sword\n\nhit 4\nwait 500\nroom -1 2").unwrap();
+        assert!(matches!(script[0], ScriptCommands::Walk { tiles: 3, .. }));
+        assert!(matches!(script[1], ScriptCommands::Hit(4)));
+        assert!(matches!(script[3], ScriptCommands::Room(r) if r.x == -1 && r.y == 2));
+        assert!(matches!(parse_script("walk up 3"), Err(e) if e.line == 1));
+        assert!(matches!(parse_script("hit 4\nfly"), Err(e) if e.line == 2));
+    }
+}
@@ -5,7 +5,7 @@ use encoding_rs::SHIFT_JIS;
 use encoding_rs_io::DecodeReaderBytesBuilder;
 use constcat::concat;
 use crate::server::{ROOM_EXTENSION, ROOM_COORD_SEPARATOR};
-use crate::soaprun::rooms::{CLIENT_ROOM_HEIGHT, CLIENT_ROOM_WIDTH};
+use soaprun::rooms::{CLIENT_ROOM_HEIGHT, CLIENT_ROOM_WIDTH};
 
 #[derive(Debug)]
 pub enum LegacyRoomReadErrors
@@ -0,0 +1,6 @@
+pub mod client;
+pub mod legacy_map_conversion;
+pub mod load_test;
+pub mod logging;
+pub mod replay;
+pub mod server;
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::thread_rng;
+
+use crate::client::{ClientError, SoaprunClient};
+use crate::server::Transports;
+use soaprun::packets::ReadPacketErrors;
+use soaprun::soaprunners::SoaprunnerSprites;
+
+//how long a bot waits before reconnecting after something went wrong, so it doesn't hammer the server
+const RETRY_DELAY : Duration = Duration::from_secs(1);
+//how often the server's CPU time gets sampled
+const CPU_SAMPLE_INTERVAL : Duration = Duration::from_secs(1);
+//the kernel always reports /proc/<pid>/stat times in USER_HZ, which is 100 pretty much everywhere
+const USER_HZ : f64 = 100.0;
+
+pub struct LoadTestOptions {
+    pub address: String,
+    pub transport: Transports,
+    pub players: usize,
+    pub duration: Duration,
+    //time between each bot's packets
+    pub interval: Duration,
+    //time between starting each bot
+    pub ramp: Duration,
+    //the server's process id, for measuring its CPU usage (Linux only)
+    pub server_pid: Option<u32>
+}
+
+#[derive(Default)]
+struct BotReport {
+    round_trips: Vec<Duration>,
+    //why each session ended
+    endings: BTreeMap<String, usize>
+}
+
+fn error_reason(error: &ClientError) -> String {
+    match error {
+        ClientError::IOError(e) | ClientError::ReadPacketError(ReadPacketErrors::IOError(e)) => format!("disconnected ({:?})", e.kind()),
+        ClientError::ReadPacketError(_) => "invalid packet".to_owned(),
+        ClientError::ServerFull => "server full".to_owned(),
+        ClientError::UnexpectedPacket { expected, got } => format!("expected {expected}, got {got}"),
+        ClientError::WrongProtocol { .. } => "wrong protocol".to_owned(),
+        ClientError::ConnectionTestMismatch => "bad connection test".to_owned(),
+        ClientError::Blocked { .. } => "blocked".to_owned(),
+    }
+}
+
+//one connection, from the handshake until the game ends or time's up
+fn run_session(options: &LoadTestOptions, deadline: Instant, round_trips: &mut Vec<Duration>) -> Result<&'static str, ClientError> {
+    let mut rng = thread_rng();
+    let mut client = SoaprunClient::connect(&options.address, options.transport)?;
+    while Instant::now() < deadline && client.is_playing() {
+        client.random_step(&mut rng)?;
+        let sent = Instant::now();
+        client.send_position()?;
+        round_trips.push(sent.elapsed());
+        thread::sleep(options.interval);
+    }
+    let ending = match client.state {
+        SoaprunnerSprites::Dying => "died",
+        SoaprunnerSprites::Winning => "won",
+        _ => "finished",
+    };
+    match client.bye() {
+        Ok(()) => Ok(ending),
+        //the server hangs up right after telling us we died to a bad movement
+        //anything else (timeouts, resets, etc.) could've happened to anyone, so it's reported as is
+        Err(e) if ending == "died" && closed_by_server(&e) => Ok("movement rejected"),
+        Err(e) => Err(e),
+    }
+}
+fn closed_by_server(error: &ClientError) -> bool {
+    matches!(error, ClientError::IOError(e) | ClientError::ReadPacketError(ReadPacketErrors::IOError(e))
+        if e.kind() == ErrorKind::UnexpectedEof)
+}
+
+//keeps one player connected until the deadline, reconnecting whenever a session ends
+fn run_bot(options: &LoadTestOptions, deadline: Instant) -> BotReport {
+    let mut report = BotReport::default();
+    while Instant::now() < deadline {
+        let ending = match run_session(options, deadline, &mut report.round_trips) {
+            Ok(ending) => ending.to_owned(),
+            Err(e) => {
+                thread::sleep(RETRY_DELAY);
+                error_reason(&e)
+            },
+        };
+        *report.endings.entry(ending).or_default() += 1;
+    }
+    report
+}
+
+//user + system time, in seconds
+fn read_cpu_time(pid: u32) -> Option<f64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    //the process name can have spaces in it, so skip past it first
+    let fields = Vec::from_iter(stat.rsplit_once(')')?.1.split_whitespace());
+    //utime and stime are fields 14 and 15, counting from the pid
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) / USER_HZ)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    match sorted.len() {
+        0 => Duration::ZERO,
+        n => sorted[((n - 1) as f64 * p).round() as usize],
+    }
+}
+
+pub fn load_test(options: LoadTestOptions) {
+    println!("Starting {} bots against {} over {}...", options.players, options.address, options.transport.label());
+    let start = Instant::now();
+    let deadline = start + options.duration;
+
+    let reports = thread::scope(|scope| {
+        let options = &options;
+        let mut bots = Vec::with_capacity(options.players);
+        for _ in 0..options.players {
+            bots.push(scope.spawn(move || run_bot(options, deadline)));
+            thread::sleep(options.ramp);
+        }
+
+        //percent of one core, like top
+        let mut cpu_samples = Vec::new();
+        if let Some(pid) = options.server_pid {
+            let mut last = read_cpu_time(pid).map(|t| (Instant::now(), t));
+            while Instant::now() < deadline {
+                thread::sleep(CPU_SAMPLE_INTERVAL);
+                let now = read_cpu_time(pid).map(|t| (Instant::now(), t));
+                if let (Some((last_at, last_cpu)), Some((now_at, now_cpu))) = (last, now) {
+                    cpu_samples.push(100.0 * (now_cpu - last_cpu) / (now_at - last_at).as_secs_f64());
+                }
+                last = now;
+            }
+        }
+        (Vec::from_iter(bots.into_iter().filter_map(|b| b.join().ok())), cpu_samples)
+    });
+    let (reports, cpu_samples) = reports;
+
+    let mut round_trips = Vec::new();
+    let mut endings: BTreeMap<String, usize> = BTreeMap::new();
+    for report in reports {
+        round_trips.extend(report.round_trips);
+        for (ending, count) in report.endings {
+            *endings.entry(ending).or_default() += count;
+        }
+    }
+    round_trips.sort();
+
+    println!("Ran {} bots for {:.1}s, {} Flds received", options.players, start.elapsed().as_secs_f64(), round_trips.len());
+    println!("Flds round trip: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(&round_trips, 0.5),
+        percentile(&round_trips, 0.9),
+        percentile(&round_trips, 0.99),
+        round_trips.last().copied().unwrap_or_default());
+    match (options.server_pid, cpu_samples.is_empty()) {
+        (None, _) => { },
+        (Some(pid), true) => println!("Couldn't read the CPU usage of process {pid}"),
+        (Some(_), false) => println!("Server CPU: {:.1}% average, {:.1}% peak (100% is one core)",
+            cpu_samples.iter().sum::<f64>() / cpu_samples.len() as f64,
+            cpu_samples.iter().copied().fold(0.0, f64::max)),
+    }
+    println!("Sessions ended by:");
+    for (ending, count) in endings {
+        println!("  {ending}: {count}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::percentile;
+
+    #[test]
+    fn percentiles_pick_the_nearest_sample() {
+        let samples = Vec::from_iter((1..=100).map(Duration::from_millis));
+        assert_eq!(percentile(&samples, 0.5), Duration::from_millis(51));
+        assert_eq!(percentile(&samples, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&samples, 1.0), Duration::from_millis(100));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+}
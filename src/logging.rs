@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::Deserialize;
+use serde_json::{Map, Number};
+
+//log targets for each part of the server, so they can be filtered separately
+pub const SERVER : &str = "server";
+//connections, packets, and anything else talking to the outside world
+pub const NET : &str = "net";
+pub const MOVEMENT : &str = "movement";
+pub const ENTITIES : &str = "entities";
+pub const ROOMS : &str = "rooms";
+pub const ADMIN : &str = "admin";
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+pub enum LogFormats {
+    //human readable, warnings and errors go to stderr
+    #[default]
+    Text,
+    //one JSON object per line on stdout, including any key-value fields (player, peer, packet, etc.)
+    Json
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogConfig {
+    //Off, Error, Warn, Info, Debug, or Trace
+    #[serde(default = "default_log_level")]
+    pub level: LevelFilter,
+    //overrides the level for specific targets, like { "movement": "Debug" }
+    #[serde(default)]
+    pub targets: HashMap<String, LevelFilter>,
+    #[serde(default)]
+    pub format: LogFormats
+}
+fn default_log_level() -> LevelFilter { LevelFilter::Info }
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: default_log_level(),
+            targets: HashMap::new(),
+            format: LogFormats::default()
+        }
+    }
+}
+
+struct Logger {
+    level: LevelFilter,
+    targets: HashMap<String, LevelFilter>,
+    format: LogFormats
+}
+impl Logger {
+    //"net" also covers "net::whatever", and the most specific target wins
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let mut target = target;
+        loop {
+            if let Some(level) = self.targets.get(target) {
+                return *level
+            }
+            match target.rsplit_once("::") {
+                Some((parent, _)) => target = parent,
+                None => return self.level,
+            }
+        }
+    }
+    fn write_text(&self, record: &Record) {
+        let line = format!("{} {:<5} [{}] {}",
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            record.target(),
+            record.args());
+        //ignoring errors since there's nowhere left to report them
+        let _ = match record.level() {
+            Level::Error | Level::Warn => writeln!(std::io::stderr().lock(), "{line}"),
+            _ => writeln!(std::io::stdout().lock(), "{line}"),
+        };
+    }
+    fn write_json(&self, record: &Record) {
+        let mut object = Map::new();
+        object.insert("time".to_owned(), chrono::Utc::now().to_rfc3339().into());
+        object.insert("level".to_owned(), record.level().as_str().into());
+        object.insert("target".to_owned(), record.target().into());
+        object.insert("message".to_owned(), record.args().to_string().into());
+        let _ = record.key_values().visit(&mut JsonVisitor(&mut object));
+        let _ = writeln!(std::io::stdout().lock(), "{}", serde_json::Value::Object(object));
+    }
+}
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+        match self.format {
+            LogFormats::Text => self.write_text(record),
+            LogFormats::Json => self.write_json(record),
+        }
+    }
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, serde_json::Value>);
+impl<'kvs> VisitSource<'kvs> for JsonVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            b.into()
+        } else if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_f64().and_then(Number::from_f64) {
+            n.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+pub fn init(config: &LogConfig) -> Result<(), SetLoggerError> {
+    let max_level = config.targets.values().fold(config.level, |max, l| max.max(*l));
+    log::set_boxed_logger(Box::new(Logger {
+        level: config.level,
+        targets: config.targets.clone(),
+        format: config.format
+    }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+
+    use super::{LogConfig, Logger};
+
+    #[test]
+    fn targets_override_the_default_level() {
+        let config: LogConfig = serde_json::from_str(r#"{
+            "level": "warn",
+            "targets": { "movement": "Debug", "net": "Off" }
+        }"#).unwrap();
+        let logger = Logger { level: config.level, targets: config.targets, format: config.format };
+        assert_eq!(logger.level_for("movement"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("net"), LevelFilter::Off);
+        assert_eq!(logger.level_for("net::dispatch"), LevelFilter::Off);
+        assert_eq!(logger.level_for("rooms"), LevelFilter::Warn);
+    }
+}
@@ -1,13 +1,15 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{fs, process::exit};
 use clap::{Parser, Subcommand};
+use log::{error, info};
+use rand::thread_rng;
 
-mod legacy_map_conversion;
-mod soaprun;
-mod server;
-
-use server::ServerConfig;
-use server::SoaprunServer;
+use soapdispenser::{client, legacy_map_conversion, load_test, logging, replay};
+use soapdispenser::client::SoaprunClient;
+use soapdispenser::server::ServerConfig;
+use soapdispenser::server::SoaprunServer;
+use soapdispenser::server::Transports;
 
 #[derive(Subcommand)]
 #[clap(rename_all="PascalCase")]
@@ -16,11 +18,87 @@ enum Actions {
         in_dir: PathBuf,
         conversion_map_path: PathBuf,
         out_dir : Option<PathBuf>
+    },
+    //plays captured sessions back against a fresh server with the same rooms and entities
+    Replay {
+        #[arg(required = true)]
+        captures: Vec<PathBuf>,
+        //wait between packets like the original players did, instead of going as fast as possible
+        #[arg(long = "realtime")]
+        realtime: bool
+    },
+    //connects to a server like the real client would, then walks around randomly (or follows a script)
+    Bot {
+        address: String,
+        #[arg(long = "websocket")]
+        websocket: bool,
+        #[arg(long = "script")]
+        script: Option<PathBuf>,
+        //how many steps to take when walking randomly, forever if not given
+        #[arg(long = "steps")]
+        steps: Option<usize>,
+        //milliseconds between each packet
+        #[arg(long = "interval", default_value_t = 100)]
+        interval: u64
+    },
+    //connects lots of bots at once, then reports how quickly the server responded
+    LoadTest {
+        address: String,
+        #[arg(long = "players", default_value_t = 64)]
+        players: usize,
+        //seconds
+        #[arg(long = "duration", default_value_t = 60)]
+        duration: u64,
+        //milliseconds between each bot's packets
+        #[arg(long = "interval", default_value_t = 100)]
+        interval: u64,
+        //milliseconds between starting each bot
+        #[arg(long = "ramp", default_value_t = 50)]
+        ramp: u64,
+        #[arg(long = "websocket")]
+        websocket: bool,
+        //the server's process id, to measure its CPU usage
+        #[arg(long = "pid")]
+        pid: Option<u32>
     }
 }
 
 const CONFIG_PATH : &str = "config.json";
 
+fn run_bot(address: &str, websocket: bool, script: Option<PathBuf>, steps: Option<usize>, interval: Duration) -> Result<(), Box<dyn std::error::Error>>
+{
+    let script = match script {
+        Some(path) => Some(client::parse_script(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let transport = if websocket { Transports::WebSocket } else { Transports::Tcp };
+    let mut bot = SoaprunClient::connect(address, transport)?;
+    println!("Connected to {address} over {}, loaded {} rooms", transport.label(), bot.rooms.len());
+    match &script {
+        Some(script) => bot.run_script(script, interval)?,
+        None => bot.random_walk(steps, interval, &mut thread_rng())?,
+    }
+    let items = Vec::from_iter(bot.items.iter_names().map(|(name, _)| name));
+    println!("Finished at {} as {:?} holding [{}]", bot.position, bot.state, items.join(", "));
+    bot.bye()?;
+    Ok(())
+}
+
+//only the server (and anything pretending to be one) needs this, the bots can run anywhere
+fn load_config(config_path: &Path) -> ServerConfig
+{
+    let config_str = fs::read_to_string(config_path).unwrap_or_else(|_error|
+        {
+            println!("Couldn't find config file at location {:?}. Is your working directory correct?", config_path);
+            exit(1);
+        });
+    serde_json::from_str(&config_str).unwrap_or_else(|error|
+        {
+            println!("Malformed config file: {:?}", error);
+            exit(2);
+        })
+}
+
 #[derive(Parser)]
 struct Args
 {
@@ -36,38 +114,96 @@ fn main()
 
     let config_path = args.config.unwrap_or(PathBuf::from(CONFIG_PATH));
 
-    let config_str = fs::read_to_string(&config_path).unwrap_or_else(|_error|
-        {
-            println!("Couldn't find config file at location {:?}. Is your working directory correct?", config_path);
-            exit(1);
-        });
-    let config:ServerConfig = serde_json::from_str(&config_str).unwrap_or_else(|error| 
-        {
-            println!("Malformed config file: {:?}", error);
-            exit(2);
-        });
-
     if let Some(action) = args.action {
         match action {
             Actions::ConvertRooms { in_dir, conversion_map_path, out_dir } =>
             {
-                let _ = legacy_map_conversion::convert_rooms(&in_dir, &conversion_map_path, &out_dir.unwrap_or(config.room_directory));
+                let out_dir = out_dir.unwrap_or_else(|| load_config(&config_path).room_directory);
+                let _ = legacy_map_conversion::convert_rooms(&in_dir, &conversion_map_path, &out_dir);
+            },
+            Actions::Replay { captures, realtime } =>
+            {
+                let mut config = load_config(&config_path);
+                //don't record the replay, or log it as if it were real players
+                config.capture = None;
+                config.event_log_path = None;
+                //start from the map as it was in the room directory, and don't save over the real one
+                config.map_save_path = None;
+                //every recorded session gets in, however many there were at once
+                config.ban_list_path = None;
+                config.allow_list_path = None;
+                config.max_connections_per_ip = 0;
+                config.max_new_connections_per_ip = 0;
+                //the replay decides when packets arrive
+                config.idle_timeout = 0;
+                match replay::replay(&config, &captures, realtime) {
+                    Ok(summary) => {
+                        println!("Replayed {} sessions: sent {} packets, {}/{} responses matched, {} missing",
+                            summary.sessions, summary.sent, summary.matched, summary.compared, summary.missing);
+                        for (category, count) in &summary.differences {
+                            println!("  {category}: {count} differences");
+                        }
+                        if !summary.is_identical() || summary.missing > 0 {
+                            exit(4);
+                        }
+                    },
+                    Err(e) => {
+                        println!("Error: {e}");
+                        exit(1);
+                    },
+                }
+            },
+            Actions::Bot { address, websocket, script, steps, interval } =>
+            {
+                if let Err(e) = run_bot(&address, websocket, script, steps, Duration::from_millis(interval)) {
+                    println!("Error: {e}");
+                    exit(1);
+                }
+            },
+            Actions::LoadTest { address, players, duration, interval, ramp, websocket, pid } =>
+            {
+                load_test::load_test(load_test::LoadTestOptions {
+                    address,
+                    transport: if websocket { Transports::WebSocket } else { Transports::Tcp },
+                    players,
+                    duration: Duration::from_secs(duration),
+                    interval: Duration::from_millis(interval),
+                    ramp: Duration::from_millis(ramp),
+                    server_pid: pid
+                });
             },
         }
         exit(0);
     }
-    
-    println!("Starting server...");
+
+    let config = load_config(&config_path);
+    if let Err(e) = logging::init(&config.logging) {
+        eprintln!("Unable to set up logging: {e}");
+        exit(1);
+    }
+
+    info!(target: logging::SERVER, "Starting server...");
     match SoaprunServer::new(&config) {
         Ok(server) => {
+            let handler_res = ctrlc::set_handler(move || {
+                //a second Ctrl+C means the user doesn't want to wait
+                if server.is_shutting_down() {
+                    error!(target: logging::SERVER, "Forcing the server to close!");
+                    exit(3);
+                }
+                server.shutdown();
+            });
+            if let Err(e) = handler_res {
+                error!(target: logging::SERVER, "Unable to handle Ctrl+C, the server won't be able to shut down cleanly: {e}");
+            }
             match server.start_server(config.address) {
                 Ok(()) => { }, //it worked, lol
-                Err(e) => eprintln!("Error: {e}"),
+                Err(e) => error!(target: logging::SERVER, "Error: {e}"),
             }
         },
         Err(e) => {
-            eprintln!("Error: {e}");
+            error!(target: logging::SERVER, "Error: {e}");
         },
     };
-    println!("Server closed!");
+    info!(target: logging::SERVER, "Server closed!");
 }
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::server::{decode_hex, encode_hex, CaptureDirections, CaptureRecord, Clock, ManualClock, MemoryStream, PacketStream, NewServerError, ServerConfig, SoaprunServer};
+use soaprun::packets::PACKET_TYPE_FIELDS;
+
+//how long to wait for the server to send something the recording says it sent
+const RESPONSE_TIMEOUT : Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("An error occured while reading {}: `{error}`", path.display())]
+    FileLoadError {
+        path: PathBuf,
+        error: io::Error
+    },
+    #[error("Line {line} of {} isn't a valid capture record: `{error}`", path.display())]
+    DeserializeError {
+        path: PathBuf,
+        line: usize,
+        error: serde_json::Error
+    },
+    #[error("Line {line} of {} has invalid hex data", path.display())]
+    InvalidHex {
+        path: PathBuf,
+        line: usize
+    },
+    #[error("Couldn't start the server: {0}")]
+    ServerError(#[from] NewServerError)
+}
+
+struct ReplayRecord {
+    record: CaptureRecord,
+    data: Vec<u8>
+}
+
+fn load_capture(path: &Path) -> Result<Vec<ReplayRecord>, ReplayError> {
+    let contents = fs::read_to_string(path).map_err(|error| ReplayError::FileLoadError { path: path.to_owned(), error })?;
+    let mut records = Vec::new();
+    for (i, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let record: CaptureRecord = serde_json::from_str(line)
+            .map_err(|error| ReplayError::DeserializeError { path: path.to_owned(), line: i + 1, error })?;
+        let Some(data) = decode_hex(&record.data) else {
+            return Err(ReplayError::InvalidHex { path: path.to_owned(), line: i + 1 })
+        };
+        records.push(ReplayRecord { record, data });
+    }
+    Ok(records)
+}
+
+//a Flds packet split into the parts that can be compared separately
+struct Fields<'a> {
+    state: u8,
+    color: u8,
+    items: u8,
+    weather: u8,
+    //indexed by player/entity number, everything after the number is compared as one
+    soaprunners: BTreeMap<u8, &'a [u8]>,
+    entities: BTreeMap<u8, &'a [u8]>,
+    tiles: Vec<&'a [u8]>
+}
+
+//None if it's not a Flds packet, or it's malformed
+fn parse_fields(packet: &[u8]) -> Option<Fields<'_>> {
+    let (&packet_type, data) = packet.split_first_chunk::<4>()?;
+    if packet_type != PACKET_TYPE_FIELDS {
+        return None
+    }
+    let (&[state, color, items, soaprunner_count, entity_count, tile_count, weather], mut data) = data.split_first_chunk::<7>()?;
+    //both soaprunners and entities are 5 bytes, then a list of movements
+    let mut read_units = |count: u8| -> Option<BTreeMap<u8, &[u8]>> {
+        let mut units = BTreeMap::new();
+        for _ in 0..count {
+            let movements = *data.get(5)? as usize;
+            let (unit, rest) = data.split_at_checked(6 + movements * 4)?;
+            units.insert(unit[0], &unit[1..]);
+            data = rest;
+        }
+        Some(units)
+    };
+    let soaprunners = read_units(soaprunner_count)?;
+    let entities = read_units(entity_count)?;
+    if data.len() != tile_count as usize * 6 {
+        return None
+    }
+    Some(Fields {
+        state, color, items, weather,
+        soaprunners,
+        entities,
+        tiles: data.chunks_exact(6).collect()
+    })
+}
+
+fn diff_units(kind: &str, expected: &BTreeMap<u8, &[u8]>, got: &BTreeMap<u8, &[u8]>, differences: &mut Vec<String>) {
+    for i in expected.keys().chain(got.keys().filter(|i| !expected.contains_key(i))) {
+        match (expected.get(i), got.get(i)) {
+            (Some(e), Some(g)) if e != g => differences.push(format!("{kind} {i}")),
+            (Some(_), None) => differences.push(format!("{kind} {i} (missing)")),
+            (None, Some(_)) => differences.push(format!("{kind} {i} (extra)")),
+            _ => { }
+        }
+    }
+}
+
+//returns what's different, with the categories used in the summary
+fn diff_fields(expected: &Fields, got: &Fields) -> Vec<(&'static str, String)> {
+    let mut differences = Vec::new();
+    for (category, e, g) in [("state", expected.state, got.state), ("color", expected.color, got.color),
+        ("items", expected.items, got.items), ("weather", expected.weather, got.weather)] {
+        if e != g {
+            differences.push((category, format!("{category} {e} != {g}")));
+        }
+    }
+    let mut units = Vec::new();
+    diff_units("soaprunner", &expected.soaprunners, &got.soaprunners, &mut units);
+    differences.extend(units.drain(..).map(|d| ("soaprunners", d)));
+    diff_units("entity", &expected.entities, &got.entities, &mut units);
+    differences.extend(units.drain(..).map(|d| ("entities", d)));
+    if expected.tiles != got.tiles {
+        differences.push(("tiles", format!("{} tiles != {} tiles", expected.tiles.len(), got.tiles.len())));
+    }
+    differences
+}
+
+fn packet_name(packet: &[u8]) -> String {
+    match packet.get(0..4) {
+        Some(t) => String::from_utf8_lossy(t).into_owned(),
+        None => encode_hex(packet),
+    }
+}
+
+#[derive(Default)]
+pub struct ReplaySummary {
+    pub sessions: usize,
+    pub sent: usize,
+    pub compared: usize,
+    pub matched: usize,
+    //packets the recording has, but the server never sent
+    pub missing: usize,
+    //how many packets differed in each category ("state", "entities", "Prtc", etc.)
+    pub differences: BTreeMap<String, usize>
+}
+impl ReplaySummary {
+    pub fn is_identical(&self) -> bool {
+        self.compared == self.matched
+    }
+}
+
+struct ReplaySession {
+    client: MemoryStream,
+    handler: JoinHandle<()>,
+    //packets received so far, to tell the user where things went wrong
+    received: usize
+}
+
+//plays every session back against a fresh server, printing each difference as it's found
+//entities are updated in step with the recorded times instead of the wall clock, so with an rng_seed every replay is the same
+//player colors and entity movement are still random compared to the recording, so expect those to differ
+pub fn replay(config: &ServerConfig, captures: &[PathBuf], realtime: bool) -> Result<ReplaySummary, ReplayError> {
+    let mut records = Vec::new();
+    for path in captures {
+        records.append(&mut load_capture(path)?);
+    }
+    //daily captures are already in order, but multiple session files won't be
+    records.sort_by_key(|r| r.record.time);
+
+    let clock = Arc::new(ManualClock::default());
+    let server = SoaprunServer::with_clock(config, clock.clone())?;
+    let clock_start = clock.now();
+
+    let mut summary = ReplaySummary::default();
+    let mut sessions: HashMap<String, ReplaySession> = HashMap::new();
+    let start = Instant::now();
+    let first_time = records.first().map(|r| r.record.time);
+    for ReplayRecord { record, data } in records {
+        if let Some(first_time) = first_time {
+            server.simulate_until(clock_start + (record.time - first_time).to_std().unwrap_or_default());
+        }
+        let session = sessions.entry(record.session.clone()).or_insert_with(|| {
+            let (mut client, server_side) = MemoryStream::pair();
+            client.set_read_timeout(Some(RESPONSE_TIMEOUT));
+            summary.sessions += 1;
+            println!("Starting session {} (recorded as player {})", record.session, record.player);
+            ReplaySession {
+                client,
+                handler: thread::spawn(move || server.client_handler(Box::new(server_side), 0)),
+                received: 0
+            }
+        });
+        match record.direction {
+            CaptureDirections::In => {
+                if let (true, Some(first_time)) = (realtime, first_time) {
+                    let offset = (record.time - first_time).to_std().unwrap_or_default();
+                    thread::sleep(offset.saturating_sub(start.elapsed()));
+                }
+                //if the server hung up, the missing responses will say so
+                if session.client.write_packet(data).is_ok() {
+                    summary.sent += 1;
+                }
+            },
+            CaptureDirections::Out => {
+                session.received += 1;
+                let position = format!("{} packet {}", record.session, session.received);
+                let got = match session.client.read_packet() {
+                    Ok(got) => got,
+                    Err(e) => {
+                        println!("{position}: expected {}, but got nothing ({e})", packet_name(&data));
+                        summary.missing += 1;
+                        continue
+                    }
+                };
+                summary.compared += 1;
+                if got == data {
+                    summary.matched += 1;
+                    continue
+                }
+                match (parse_fields(&data), parse_fields(&got)) {
+                    (Some(expected), Some(got)) => {
+                        let differences = diff_fields(&expected, &got);
+                        let mut categories = Vec::from_iter(differences.iter().map(|(c, _)| *c));
+                        categories.dedup();
+                        for category in categories {
+                            *summary.differences.entry(category.to_owned()).or_default() += 1;
+                        }
+                        println!("{position}: Flds differs: {}", Vec::from_iter(differences.into_iter().map(|(_, d)| d)).join(", "));
+                    },
+                    _ => {
+                        let name = packet_name(&data);
+                        println!("{position}: expected {name} {}, got {}", encode_hex(&data), encode_hex(&got));
+                        *summary.differences.entry(name).or_default() += 1;
+                    },
+                }
+            },
+        }
+    }
+
+    //hanging up makes each handler clean up, same as a real disconnect
+    let handlers = Vec::from_iter(sessions.into_values().map(|s| s.handler));
+    for handler in handlers {
+        let _ = handler.join();
+    }
+    server.shutdown();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_fields, parse_fields};
+
+    #[test]
+    fn fields_are_compared_by_part() {
+        //green, no items, one soaprunner (#2) standing still, one entity (#0) moving one tile, no tiles
+        let expected = b"Flds\x01\x01\x00\x01\x01\x00\x00\
+            \x02\x00\x01\x01\x00\x01\x05\x00\x06\x00\
+            \x00\x00\x01\x03\x00\x02\x05\x00\x06\x00\x06\x00\x06\x00";
+        let mut got = *expected;
+        //pink instead of green, and the entity went the other way
+        got[5] = 2;
+        got[31] = 4;
+
+        let expected = parse_fields(expected).unwrap();
+        let got = parse_fields(&got).unwrap();
+        assert_eq!(expected.soaprunners.len(), 1);
+        assert_eq!(expected.entities.len(), 1);
+        let categories = Vec::from_iter(diff_fields(&expected, &got).into_iter().map(|(c, _)| c));
+        assert_eq!(categories, ["color", "entities"]);
+
+        assert!(parse_fields(b"Flds\x01\x01\x00\x01\x00\x00\x00\x02").is_none());
+        assert!(parse_fields(b"WLCM").is_none());
+    }
+}
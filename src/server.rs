@@ -1,21 +1,26 @@
 use std::cmp::Reverse;
 use std::collections::{BTreeMap, BinaryHeap, HashMap};
-use std::net::{TcpListener, ToSocketAddrs};
+use std::fs::File;
+use std::net::{Shutdown, SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::PathBuf;
 use std::time::Duration;
-use std::thread;
-use std::sync::atomic::AtomicUsize;
+use std::thread::{self, JoinHandle};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
 use std::sync::Arc;
 
+use log::{error, info, warn};
 use parking_lot::{Mutex, RwLock};
 use rand::distributions::{Distribution, WeightedIndex};
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use thiserror::Error;
 
-use crate::soaprun::packets::PROTOCOL_BUFFER_SIZE;
-use crate::soaprun::map_attributes::MapAttributes;
-use crate::soaprun::rooms::*;
-use crate::soaprun::soaprunners::*;
-use crate::soaprun::*;
+use crate::logging::{ENTITIES, NET, ROOMS, SERVER};
+use soaprun::map_attributes::MapAttributes;
+use soaprun::packets::Capabilities;
+use soaprun::rooms::*;
+use soaprun::soaprunners::*;
+use soaprun::*;
 
 mod config;
 pub use config::*;
@@ -23,41 +28,102 @@ mod rooms;
 use rooms::*;
 pub use rooms::{ROOM_EXTENSION, ROOM_COORD_SEPARATOR};
 mod clients;
-use clients::*;
+pub use clients::{Client, MovementValidationErrors};
 mod entities;
 use entities::*;
 mod position_extensions;
 mod stream;
 pub use stream::*;
+mod http;
+mod dispatch;
+mod maintenance;
+pub use maintenance::*;
+mod shutdown;
+mod admin;
+mod access;
+pub use access::*;
+mod limits;
+use limits::*;
+mod events;
+use events::*;
+mod metrics;
+use metrics::*;
+mod capture;
+pub use capture::*;
+mod clock;
+pub use clock::*;
+mod session;
+pub use session::*;
+#[cfg(test)]
+mod test_harness;
 
-pub const PROTOCOL_NAME : &[u8; PROTOCOL_BUFFER_SIZE] = b"Soaprun\0";
-pub const PROTOCOL_VERSION : u16 = 64;
+pub use soaprun::packets::{PROTOCOL_NAME, PROTOCOL_VERSION};
+//sent to game versions we don't accept, no version of the client has ever expected it so they all show a version mismatch
+pub const REJECTED_PROTOCOL_VERSION : u16 = 0;
 
 pub struct SoaprunServer
 {
     //individual rooms need to be locked when tiles are updated
     rooms: HashMap<RoomCoordinates, RwLock<Room>>,
+    //the rooms as they were in the room directory, so we know what to save
+    original_rooms: HashMap<RoomCoordinates, Room>,
+    map_save_path: Option<PathBuf>,
+    event_log: Option<Mutex<File>>,
+    capture: Option<Capture>,
+    autosave_interval: u64,
     //the default room and map attributes never change, so no lock is needed
     default_room: Room,
     map_attributes: MapAttributes,
     
     players_with_shield: AtomicUsize,
+    full_server_behavior: FullServerBehaviors,
+    out_of_phase_policy: OutOfPhasePolicies,
+    accepted_game_versions: Option<Vec<u16>>,
+    //what Caps can turn on
+    capabilities: Capabilities,
+    metrics: Metrics,
 
     entity_update_rate: Duration,
+    clock: Arc<dyn Clock>,
+    //everything random (entity movement, player colors) comes from here, so a seed makes runs repeatable
+    rng: Mutex<StdRng>,
     //the number of entities is fixed, so we never need to lock the collection as a whole, just the elements
     entities: Vec<RwLock<Entity>>,
 
     connection_timeout: u64,
     idle_timeout: u64,
+    handshake_timeout: u64,
+    max_connections_per_ip: usize,
+    max_new_connections_per_ip: usize,
+    connections: Mutex<ConnectionTracker>,
     max_player_movement_nodes_per_packet: usize,
     max_player_distance_per_movement_node: usize,
     max_player_distance_per_packet: usize,
 
+    dispatch: Option<DispatchConfig>,
+    maintenance_path: Option<PathBuf>,
+    admin_address: Option<String>,
+    metrics_address: Option<String>,
+    ban_list_path: Option<PathBuf>,
+    allow_list_path: Option<PathBuf>,
+    access_lists: RwLock<AccessLists>,
+
     //player number heap is only accessed during joins/leaves, so mutex it is
     player_numbers: Mutex<BinaryHeap<Reverse<usize>>>,
     //the entire player list is only locked during joins/leaves
     //individual players may be locked frequently to update their state
-    players: RwLock<BTreeMap<usize, Arc<RwLock<Client>>>>
+    players: RwLock<BTreeMap<usize, Arc<RwLock<Client>>>>,
+    //removing a player from here and interrupting them is how other threads tell a player to leave
+    interrupts: Mutex<HashMap<usize, Box<dyn Interrupt>>>,
+    //connections that haven't made it to client_handler yet (still being probed or upgraded to WebSocket)
+    //keyed by the order they were accepted in, since they don't have a player number
+    pending_interrupts: Mutex<HashMap<u64, Box<dyn Interrupt>>>,
+    pending_count: AtomicU64,
+
+    shutting_down: AtomicBool,
+    listen_address: Mutex<Option<SocketAddr>>,
+    //only one save can happen at a time
+    save_lock: Mutex<()>
 }
 #[derive(Error, Debug)]
 pub enum NewServerError {
@@ -68,11 +134,23 @@ pub enum NewServerError {
     #[error("An error occured while loading the map attributes: `{0}`")]
     MapAttributesError(#[from] std::io::Error),
     #[error("An error occured while loading the entities: `{0}`")]
-    EntityLoadError(#[from] LoadEntityError)
+    EntityLoadError(#[from] LoadEntityError),
+    #[error("An error occured while loading the saved map: `{0}`")]
+    MapSaveError(std::io::Error),
+    #[error("An error occured while loading the access lists: `{0}`")]
+    AccessListError(#[from] LoadAccessListError),
+    #[error("An error occured while opening the event log: `{0}`")]
+    EventLogError(std::io::Error),
+    #[error("An error occured while creating the capture directory: `{0}`")]
+    CaptureError(std::io::Error)
 }
 impl SoaprunServer
 {
     pub fn new(config: &ServerConfig) -> Result<&'static SoaprunServer, NewServerError>
+    {
+        SoaprunServer::with_clock(config, Arc::new(SystemClock))
+    }
+    pub fn with_clock(config: &ServerConfig, clock: Arc<dyn Clock>) -> Result<&'static SoaprunServer, NewServerError>
     {
         let mut pn = BinaryHeap::with_capacity(config.max_players as usize);
         for i in 0..config.max_players {
@@ -80,15 +158,15 @@ impl SoaprunServer
         }
 
         let mut rooms = load_rooms(&config.room_directory)?;
-        println!("Loaded {} rooms with coordinates {}", rooms.len(), rooms.iter().map(|(c, _)| { c.to_string() }).collect::<Vec<String>>().join(", ") );
+        info!(target: ROOMS, "Loaded {} rooms with coordinates {}", rooms.len(), rooms.iter().map(|(c, _)| { c.to_string() }).collect::<Vec<String>>().join(", ") );
         
         let default_room = match Room::new(config.room_directory.join(DEFAULT_ROOM_NAME)) {
             Ok(r) => r,
             Err(e) => return Err(NewServerError::LoadRoomError(LoadRoomError::NewRoomError(e))),
         };
-        println!("...Plus the default room");
+        info!(target: ROOMS, "...Plus the default room");
         let map_attributes = MapAttributes::new(&config.attributes_path)?;
-        println!("Loaded map attributes");
+        info!(target: ROOMS, "Loaded map attributes");
 
         if let Err(e) = verify_rooms(&rooms, &default_room, &config.room_verification_bounds, match config.room_verification_mode {
             RoomVerificationModes::Tiles => None,
@@ -96,37 +174,103 @@ impl SoaprunServer
         }) {
             return Err(NewServerError::RoomVerificationError(e))
         }
-        println!("Verified rooms");
+        info!(target: ROOMS, "Verified rooms");
+
+        let original_rooms = rooms.clone();
+        if let Some(path) = &config.map_save_path {
+            match load_room_overlay(path, &mut rooms) {
+                Ok(count) => info!(target: ROOMS, "Loaded {count} saved rooms"),
+                Err(e) => return Err(NewServerError::MapSaveError(e)),
+            }
+        }
 
         let rooms = HashMap::from_iter(rooms.drain().map(|(c,r)| {
             (c,RwLock::new(r))
         }));
 
         let mut entities = load_entities(&config.entity_path)?;
-        println!("Loaded {} entities", entities.len());
+        info!(target: ENTITIES, "Loaded {} entities", entities.len());
 
         let entities = Vec::from_iter(entities.drain(0..).map(|e| {
             RwLock::new(e)
         }));
 
+        let access_lists = AccessLists::load(&config.ban_list_path, &config.allow_list_path)?;
+        info!(target: NET, "Loaded {} bans", access_lists.bans.len());
+        if let Some(allows) = &access_lists.allows {
+            info!(target: NET, "Loaded {} allowed addresses, everyone else will be refused", allows.len());
+        }
+
+        let event_log = match &config.event_log_path {
+            Some(path) => match open_event_log(path) {
+                Ok(f) => Some(Mutex::new(f)),
+                Err(e) => return Err(NewServerError::EventLogError(e)),
+            },
+            None => None,
+        };
+
+        let capture = match &config.capture {
+            Some(c) => Some(Capture::new(c).map_err(NewServerError::CaptureError)?),
+            None => None,
+        };
+
+        let rng = match config.rng_seed {
+            Some(seed) => {
+                info!(target: SERVER, "Using RNG seed {seed}");
+                StdRng::seed_from_u64(seed)
+            },
+            None => StdRng::from_entropy(),
+        };
+
         let server = Box::new(SoaprunServer
             {
                 player_numbers: Mutex::new(pn),
                 players:  RwLock::new(BTreeMap::new()),
+                interrupts: Mutex::new(HashMap::new()),
+                pending_interrupts: Mutex::new(HashMap::new()),
+                pending_count: AtomicU64::new(0),
+
+                shutting_down: AtomicBool::new(false),
+                listen_address: Mutex::new(None),
+                save_lock: Mutex::new(()),
                 
                 entity_update_rate: Duration::from_millis(10),
+                clock,
+                rng: Mutex::new(rng),
                 entities: entities,
 
                 players_with_shield: AtomicUsize::new(0),
+                full_server_behavior: config.full_server_behavior,
+                out_of_phase_policy: config.out_of_phase_policy,
+                accepted_game_versions: config.accepted_game_versions.clone(),
+                capabilities: config.extensions.iter().fold(Capabilities::empty(), |c, e| c | e.capability()),
+                metrics: Metrics::default(),
 
                 connection_timeout: config.connection_timeout,
                 idle_timeout: config.idle_timeout,
+                handshake_timeout: config.handshake_timeout,
+                max_connections_per_ip: config.max_connections_per_ip,
+                max_new_connections_per_ip: config.max_new_connections_per_ip,
+                connections: Mutex::new(ConnectionTracker::default()),
 
                 max_player_movement_nodes_per_packet: config.max_player_movement_nodes_per_packet as usize,
                 max_player_distance_per_movement_node: config.max_player_distance_per_movement_node as usize,
                 max_player_distance_per_packet: config.max_player_distance_per_packet as usize,
+
+                dispatch: config.dispatch.clone(),
+                maintenance_path: config.maintenance_path.clone(),
+                admin_address: config.admin_address.clone(),
+                metrics_address: config.metrics_address.clone(),
+                ban_list_path: config.ban_list_path.clone(),
+                allow_list_path: config.allow_list_path.clone(),
+                access_lists: RwLock::new(access_lists),
                 
                 rooms: rooms,
+                original_rooms,
+                map_save_path: config.map_save_path.clone(),
+                event_log,
+                capture,
+                autosave_interval: config.autosave_interval,
                 default_room: default_room,
 
                 map_attributes: map_attributes
@@ -138,7 +282,7 @@ impl SoaprunServer
         //TODO put in config
         let weights = [1,1,1,1];
         let dist = WeightedIndex::new(&weights).unwrap();
-        choices[dist.sample(&mut thread_rng())]
+        choices[dist.sample(&mut *self.rng.lock())]
     }
     fn borrow_player(&self) -> Result<(usize, Arc<RwLock<Client>>), ()>
     {
@@ -147,14 +291,16 @@ impl SoaprunServer
             None => return Err(()),
         };
         let client = Arc::new(RwLock::new(Client::new(num, self.get_player_color())));
-        self.players.write().insert(num, client.clone());
+        let mut players = self.players.write();
+        players.insert(num, client.clone());
+        self.metrics.player_joined(players.len());
         Ok((num, client))
     }
     fn return_player(&self, client: Arc<RwLock<Client>>, num: usize) -> Result<(),()>
     {
         match self.players.write().remove(&num) {
-            Some(_) => println!("Removed player {num}"),
-            None => eprintln!("Tried to remove player {num}, but they weren't in the list...?!"),
+            Some(_) => info!(target: NET, player = num; "Removed player {num}"),
+            None => error!(target: NET, player = num; "Tried to remove player {num}, but they weren't in the list...?!"),
         };
         self.player_numbers.lock().push(Reverse(num));
         drop(client);
@@ -164,33 +310,133 @@ impl SoaprunServer
         where A : ToSocketAddrs
     {
         let listener = TcpListener::bind(address)?;
-        let _ = thread::spawn(|| { //TODO maybe close this thread properly on exit
+        *self.listen_address.lock() = Some(listener.local_addr()?);
+        if let Some(dispatch) = &self.dispatch {
+            self.start_dispatch(dispatch)?;
+        }
+        if let Some(admin_address) = &self.admin_address {
+            self.start_admin_console(admin_address)?;
+        }
+        if let Some(metrics_address) = &self.metrics_address {
+            self.start_metrics(metrics_address)?;
+        }
+        let entity_thread = thread::spawn(|| {
             self.entity_handler()
         });
-        println!("Listening on {}", listener.local_addr().unwrap());
+        if self.map_save_path.is_some() && self.autosave_interval > 0 {
+            let _ = thread::spawn(|| {
+                self.autosave_handler()
+            });
+        }
+        let mut client_threads = Vec::new();
+        info!(target: NET, "Listening on {}", listener.local_addr().unwrap());
         for acc_res in listener.incoming() {
+            if self.is_shutting_down() {
+                break
+            }
             match acc_res
             {
                 Ok(stream) => {
-                    stream.set_nodelay(true).expect("Unable to disable delay!");
-                    if self.connection_timeout > 0 {
-                        let dur = Some(Duration::from_secs(self.connection_timeout));
-                        let _ = stream.set_read_timeout(dur);
-                        let _ = stream.set_write_timeout(dur);
+                    let peer_addr = match stream.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            warn!(target: NET, "Error getting the address of an incoming connection: {:?}", e);
+                            continue;
+                        }
+                    };
+                    if let Err(reason) = self.check_access(peer_addr.ip()) {
+                        self.metrics.connection_refused(RefusalReasons::Access);
+                        info!(target: NET, peer:% = peer_addr; "Refused connection from {peer_addr}: {reason}");
+                        let _ = stream.shutdown(Shutdown::Both);
+                        continue;
+                    }
+                    //closing right away is nicer than leaving the client to time out
+                    if let ServerStatus::Closed(_) = self.get_status() {
+                        self.metrics.connection_refused(RefusalReasons::Closed);
+                        info!(target: NET, peer:% = peer_addr; "Refused connection from {peer_addr} because the server is closed");
+                        let _ = stream.shutdown(Shutdown::Both);
+                        continue;
                     }
-                    thread::spawn(|| {
-                        match probe_stream(stream)
+                    //this has to come before spawning the thread, since the whole point is not spawning too many
+                    let guard = match self.open_connection(peer_addr.ip()) {
+                        Ok(guard) => guard,
+                        Err(reason) => {
+                            self.metrics.connection_refused(RefusalReasons::Limit);
+                            warn!(target: NET, peer:% = peer_addr; "Refused connection from {peer_addr}: {reason}");
+                            let _ = stream.shutdown(Shutdown::Both);
+                            continue;
+                        }
+                    };
+                    stream.set_nodelay(true).expect("Unable to disable delay!");
+                    let connection_timeout = match self.connection_timeout {
+                        0 => None,
+                        t => Some(Duration::from_secs(t)),
+                    };
+                    let handshake_timeout = match self.handshake_timeout {
+                        0 => connection_timeout,
+                        t => Some(Duration::from_secs(t)),
+                    };
+                    let _ = stream.set_read_timeout(handshake_timeout);
+                    let _ = stream.set_write_timeout(handshake_timeout);
+                    //shutting down has to be able to stop the probe too, or it'd wait on this thread forever
+                    let pending = match stream.try_clone() {
+                        Ok(s) => Some(self.register_pending(Box::new(s))),
+                        Err(e) => {
+                            warn!(target: NET, peer:% = peer_addr; "Couldn't get an interrupt handle for {peer_addr}, shutting down will have to wait for it: {e}");
+                            None
+                        },
+                    };
+                    client_threads.retain(|t: &JoinHandle<()>| !t.is_finished());
+                    client_threads.push(thread::spawn(move || {
+                        let _guard = guard;
+                        let probed = probe_stream(stream);
+                        if let Some(id) = pending {
+                            self.unregister_pending(id);
+                        }
+                        match probed
                         {
+                            //client_handler switches to connection_timeout once the Soaprun handshake is done too
                             Ok(stream) => self.client_handler(stream, self.idle_timeout),
-                            Err(e) => eprintln!("Error probing incoming connection: {:?}", e),
+                            Err(e) => {
+                                self.metrics.connection_failed(e.transport);
+                                warn!(target: NET, peer:% = peer_addr; "{e}")
+                            },
                         }
-                    });
+                    }));
                 },
                 Err(e) => {
-                    eprintln!("Error accepting incoming connection: {:?}", e)
+                    error!(target: NET, "Error accepting incoming connection: {:?}", e)
                 }
             }
         }
+        drop(listener);
+
+        //everyone still connected has been interrupted, so this shouldn't take long
+        info!(target: SERVER, "Waiting for {} connections to close...", client_threads.len());
+        for t in client_threads {
+            let _ = t.join();
+        }
+        let _ = entity_thread.join();
+        self.save_map();
         return Ok(());
     }
+    fn autosave_handler(&self) {
+        loop {
+            thread::sleep(Duration::from_secs(self.autosave_interval));
+            //the final save is handled by start_server
+            if self.is_shutting_down() {
+                break
+            }
+            self.save_map();
+        }
+    }
+    pub fn save_map(&self) {
+        if self.map_save_path.is_none() {
+            return
+        }
+        match self.save_rooms() {
+            Ok(count) => info!(target: ROOMS, "Saved {count} modified rooms"),
+            Err(e) => error!(target: ROOMS, "Error saving the map: {e}"),
+        }
+    }
 }
\ No newline at end of file
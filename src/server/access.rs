@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use super::SoaprunServer;
+
+#[derive(Deserialize, Debug)]
+pub struct AccessEntry {
+    //either a single address ("203.0.113.7") or a CIDR range ("203.0.113.0/24")
+    #[serde(deserialize_with = "deserialize_range")]
+    pub range: IpNet,
+    #[serde(default)]
+    pub reason: Option<String>,
+    //RFC 3339 timestamp ("2024-12-31T23:59:59Z"), entries without one never expire
+    #[serde(default)]
+    pub expires: Option<DateTime<Utc>>
+}
+fn deserialize_range<'de, D>(deserializer: D) -> Result<IpNet, D::Error>
+    where D : Deserializer<'de>
+{
+    let s = String::deserialize(deserializer)?;
+    match s.parse::<IpNet>() {
+        Ok(net) => Ok(net),
+        Err(_) => match s.parse::<IpAddr>() {
+            Ok(ip) => Ok(IpNet::from(ip)),
+            Err(_) => Err(serde::de::Error::custom(format!("\"{s}\" isn't a valid address or CIDR range"))),
+        }
+    }
+}
+impl AccessEntry {
+    pub fn matches(&self, ip: &IpAddr, now: &DateTime<Utc>) -> bool {
+        self.range.contains(ip) && self.expires.is_none_or(|e| *now < e)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LoadAccessListError {
+    #[error("An error occured while loading the access list file: `{0}`")]
+    FileLoadError(#[from] io::Error),
+    #[error("An error occured while parsing the access list file: `{0}`")]
+    DeserializeError(#[from] serde_json::Error)
+}
+
+pub fn load_access_list(path: &Path) -> Result<Vec<AccessEntry>, LoadAccessListError> {
+    let list_str = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&list_str)?)
+}
+
+pub struct AccessLists {
+    pub bans: Vec<AccessEntry>,
+    //None means everyone is allowed
+    pub allows: Option<Vec<AccessEntry>>
+}
+impl AccessLists {
+    pub fn load(ban_list_path: &Option<PathBuf>, allow_list_path: &Option<PathBuf>) -> Result<AccessLists, LoadAccessListError> {
+        Ok(AccessLists {
+            bans: match ban_list_path {
+                Some(p) => load_access_list(p)?,
+                None => Vec::new(),
+            },
+            allows: match allow_list_path {
+                Some(p) => Some(load_access_list(p)?),
+                None => None,
+            }
+        })
+    }
+}
+
+pub enum AccessDenials {
+    Banned(Option<String>),
+    NotAllowed
+}
+impl std::fmt::Display for AccessDenials
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessDenials::Banned(Some(reason)) => write!(f, "banned ({reason})"),
+            AccessDenials::Banned(None) => write!(f, "banned"),
+            AccessDenials::NotAllowed => write!(f, "not on the allow list"),
+        }
+    }
+}
+
+impl SoaprunServer
+{
+    pub fn check_access(&self, ip: IpAddr) -> Result<(), AccessDenials>
+    {
+        //IPv4 clients connecting to an IPv6 listener show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+        let now = Utc::now();
+        let lists = self.access_lists.read();
+        if let Some(ban) = lists.bans.iter().find(|b| b.matches(&ip, &now)) {
+            return Err(AccessDenials::Banned(ban.reason.clone()));
+        }
+        match &lists.allows {
+            Some(allows) if !allows.iter().any(|a| a.matches(&ip, &now)) => Err(AccessDenials::NotAllowed),
+            _ => Ok(())
+        }
+    }
+    //if either list fails to load, the old lists are kept
+    pub fn reload_access_lists(&self) -> Result<(usize, Option<usize>), LoadAccessListError>
+    {
+        let lists = AccessLists::load(&self.ban_list_path, &self.allow_list_path)?;
+        let counts = (lists.bans.len(), lists.allows.as_ref().map(|a| a.len()));
+        *self.access_lists.write() = lists;
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use chrono::{TimeDelta, Utc};
+
+    use super::AccessEntry;
+
+    #[test]
+    fn ranges_parse_and_match() {
+        let entries: Vec<AccessEntry> = serde_json::from_str(r#"[
+            { "range": "203.0.113.0/24", "reason": "griefing" },
+            { "range": "198.51.100.7", "expires": "2010-05-29T00:00:00Z" },
+            { "range": "2001:db8::/32" }
+        ]"#).unwrap();
+        let now = Utc::now();
+
+        assert!(entries[0].matches(&"203.0.113.55".parse::<IpAddr>().unwrap(), &now));
+        assert!(!entries[0].matches(&"203.0.114.1".parse::<IpAddr>().unwrap(), &now));
+
+        let expired: IpAddr = "198.51.100.7".parse().unwrap();
+        assert!(!entries[1].matches(&expired, &now));
+        assert!(entries[1].matches(&expired, &(entries[1].expires.unwrap() - TimeDelta::seconds(1))));
+
+        assert!(entries[2].matches(&"2001:db8::1".parse::<IpAddr>().unwrap(), &now));
+
+        assert!(serde_json::from_str::<Vec<AccessEntry>>(r#"[{ "range": "not an ip" }]"#).is_err());
+    }
+}
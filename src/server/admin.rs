@@ -0,0 +1,248 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use log::{error, info, warn};
+
+use crate::logging::ADMIN;
+use soaprun::packets::MAX_MESSAGE_LENGTH;
+use soaprun::rooms::{RoomCoordinates, CLIENT_ROOM_HEIGHT, CLIENT_ROOM_WIDTH};
+use soaprun::soaprunners::{SoaprunnerItems, SoaprunnerSprites};
+
+use super::{item_names, Client, ServerStatus, SoaprunServer};
+
+const ADMIN_HELP : &str = "\
+Commands:
+  players                     List every connected player
+  kick <player>               Disconnect a player
+  state <player> <state>      Force a player's state (idle, walking, dying, winning, ghost)
+  reset <entity>              Put an entity back at its spawn, taking it from whoever is holding it
+  room <x> <y>                Print a room's tiles
+  say <message>               Show a message to every player whose client turned on ServerMessages
+  status                      Show whether the server is open
+  close [comment|comment...]  Close the server (needs maintenance_path), comments are split by |
+  open                        Open the server
+  save                        Save the map now
+  reload                      Reload the ban/allow lists
+  shutdown                    Shut the server down
+  quit                        Close this console";
+
+fn parse_sprite(name: &str) -> Option<SoaprunnerSprites> {
+    match name.to_ascii_lowercase().as_str() {
+        "idle" => Some(SoaprunnerSprites::Idle),
+        "walking" => Some(SoaprunnerSprites::Walking),
+        "dying" => Some(SoaprunnerSprites::Dying),
+        "winning" => Some(SoaprunnerSprites::Winning),
+        "ghost" => Some(SoaprunnerSprites::Ghost),
+        _ => None
+    }
+}
+
+fn format_items(items: SoaprunnerItems) -> String {
+    if items.is_empty() {
+        "none".to_owned()
+    } else {
+        item_names(items).join("+")
+    }
+}
+
+impl SoaprunServer
+{
+    pub fn start_admin_console(&'static self, address: &str) -> Result<(), io::Error>
+    {
+        let listener = TcpListener::bind(address)?;
+        info!(target: ADMIN, "Admin console listening on {}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for acc_res in listener.incoming() {
+                match acc_res
+                {
+                    Ok(stream) => {
+                        //there's no authentication, so only the machine running the server gets in
+                        match stream.peer_addr() {
+                            Ok(addr) if addr.ip().is_loopback() => { },
+                            Ok(addr) => {
+                                warn!(target: ADMIN, peer:% = addr; "Refused admin connection from non-local address {addr}");
+                                continue;
+                            },
+                            Err(_) => continue,
+                        }
+                        thread::spawn(move || {
+                            if let Err(e) = self.admin_handler(stream) {
+                                warn!(target: ADMIN, "Admin console error: {e}");
+                            }
+                        });
+                    },
+                    Err(e) => {
+                        error!(target: ADMIN, "Error accepting incoming admin connection: {:?}", e)
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+    fn admin_handler(&self, stream: TcpStream) -> Result<(), io::Error>
+    {
+        let peer_addr = stream.peer_addr()?;
+        info!(target: ADMIN, peer:% = peer_addr; "Admin connected from {peer_addr}");
+        let mut out = stream.try_clone()?;
+        writeln!(out, "soapdispenser admin console, type \"help\" for a list of commands")?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let args = Vec::from_iter(line.split_whitespace());
+            let Some((&command, args)) = args.split_first() else {
+                continue
+            };
+            if command == "quit" {
+                break
+            }
+            let response = self.run_admin_command(command, args, &line);
+            writeln!(out, "{}", response)?;
+            if command == "shutdown" {
+                break
+            }
+        }
+        info!(target: ADMIN, "Admin disconnected");
+        Ok(())
+    }
+    pub(super) fn run_admin_command(&self, command: &str, args: &[&str], line: &str) -> String
+    {
+        info!(target: ADMIN, "Admin command: {line}");
+        match (command, args) {
+            ("help", _) => ADMIN_HELP.to_owned(),
+            ("players", []) => {
+                let players = self.players.read();
+                if players.is_empty() {
+                    return "No players connected".to_owned()
+                }
+                Vec::from_iter(players.iter().map(|(n, p)| {
+                    let pr = p.read();
+                    format!("{n}: at {} {:?} {:?}, items: {}, kills: {}",
+                        pr.soaprunner.movements.last().unwrap(),
+                        pr.soaprunner.sprite,
+                        pr.soaprunner.color,
+                        format_items(pr.soaprunner.items),
+                        pr.kills)
+                })).join("\n")
+            },
+            ("kick", [num]) => match num.parse::<usize>() {
+                Ok(num) if self.interrupt_player(num) => format!("Kicked player {num}"),
+                Ok(num) => format!("Player {num} isn't connected"),
+                Err(_) => format!("Invalid player number \"{num}\""),
+            },
+            ("state", [num, state]) => {
+                let Ok(num) = num.parse::<usize>() else {
+                    return format!("Invalid player number \"{num}\"")
+                };
+                let Some(sprite) = parse_sprite(state) else {
+                    return format!("Invalid state \"{state}\"")
+                };
+                let Some(client) = self.players.read().get(&num).cloned() else {
+                    return format!("Player {num} isn't connected")
+                };
+                //same as going to heaven, ghosts can't hold anything
+                if matches!(sprite, SoaprunnerSprites::Ghost) {
+                    Client::return_sword(client.write(), self);
+                    Client::drop_shield(client.write(), self);
+                }
+                client.write().soaprunner.sprite = sprite;
+                format!("Player {num} is now {:?}", sprite)
+            },
+            ("reset", [index]) => {
+                let Some(entity) = index.parse::<usize>().ok().and_then(|i| self.entities.get(i).map(|e| (i, e))) else {
+                    return format!("Invalid entity \"{index}\"")
+                };
+                let (index, entity) = entity;
+                //take the entity away from anyone holding it first, otherwise it'd be duplicated
+                for (_, p) in self.players.read().iter() {
+                    let (sword, shield) = {
+                        let pr = p.read();
+                        (pr.claimed_sword == Some(index) && pr.soaprunner.items.contains(SoaprunnerItems::Sword),
+                        pr.claimed_shield == Some(index) && pr.soaprunner.items.contains(SoaprunnerItems::Shield))
+                    };
+                    if sword {
+                        Client::return_sword(p.write(), self);
+                    }
+                    if shield {
+                        Client::drop_shield(p.write(), self);
+                    }
+                }
+                let mut ew = entity.write();
+                ew.reset();
+                format!("Reset entity {index} ({:?}) to {}", ew.unit.unit_type, ew.spawn_position)
+            },
+            ("room", [x, y]) => {
+                let (Ok(x), Ok(y)) = (x.parse::<i8>(), y.parse::<i8>()) else {
+                    return format!("Invalid room coordinates ({x},{y})")
+                };
+                let coords = RoomCoordinates { x, y };
+                let data = match self.rooms.get(&coords) {
+                    Some(r) => r.read().data,
+                    None => self.default_room.data,
+                };
+                let mut response = match self.rooms.contains_key(&coords) {
+                    true => format!("Room {coords}:"),
+                    false => format!("Room {coords} (default room):"),
+                };
+                for row in data.chunks_exact(CLIENT_ROOM_WIDTH).take(CLIENT_ROOM_HEIGHT) {
+                    response.push('\n');
+                    response.push_str(&Vec::from_iter(row.iter().map(|t| format!("{:02x}", t))).join(" "));
+                }
+                response
+            },
+            ("status", []) => {
+                let status = match self.get_status() {
+                    ServerStatus::Open => "The server is open".to_owned(),
+                    ServerStatus::Closed(comments) => format!("The server is closed: {}", comments.join(" | ")),
+                };
+                format!("{status}\n{} players connected, {} turned away because it was full",
+                    self.players.read().len(),
+                    self.metrics.total_full())
+            },
+            ("say", [_, ..]) => {
+                let message = line.trim().strip_prefix("say").unwrap_or_default().trim();
+                if message.len() > MAX_MESSAGE_LENGTH {
+                    return format!("Messages can't be longer than {MAX_MESSAGE_LENGTH} bytes")
+                }
+                let sent = self.players.read().values().filter(|p| p.write().queue_message(message)).count();
+                format!("Sent to {sent} player(s)")
+            },
+            ("close", _) => {
+                let Some(path) = &self.maintenance_path else {
+                    return "Can't close the server without a maintenance_path in the config".to_owned()
+                };
+                let comments = line.trim().strip_prefix("close").unwrap_or_default().trim();
+                let contents = Vec::from_iter(comments.split('|').map(|c| c.trim())).join("\n");
+                match fs::write(path, contents) {
+                    Ok(()) => "The server is now closed".to_owned(),
+                    Err(e) => format!("Couldn't write the maintenance file: {e}"),
+                }
+            },
+            ("open", []) => {
+                let Some(path) = &self.maintenance_path else {
+                    return "Can't open the server without a maintenance_path in the config".to_owned()
+                };
+                match fs::remove_file(path) {
+                    Ok(()) => "The server is now open".to_owned(),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => "The server was already open".to_owned(),
+                    Err(e) => format!("Couldn't remove the maintenance file: {e}"),
+                }
+            },
+            ("save", []) if self.map_save_path.is_none() => "Can't save the map without a map_save_path in the config".to_owned(),
+            ("save", []) => match self.save_rooms() {
+                Ok(count) => format!("Saved {count} modified rooms"),
+                Err(e) => format!("Error saving the map: {e}"),
+            },
+            ("reload", []) => match self.reload_access_lists() {
+                Ok((bans, Some(allows))) => format!("Loaded {bans} bans and {allows} allowed addresses"),
+                Ok((bans, None)) => format!("Loaded {bans} bans"),
+                Err(e) => format!("Couldn't reload, keeping the old lists: {e}"),
+            },
+            ("shutdown", []) => {
+                self.shutdown();
+                "Shutting down".to_owned()
+            },
+            _ => format!("Unknown command or wrong arguments: \"{line}\", type \"help\" for a list of commands"),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::logging::NET;
+use super::{CaptureConfig, CaptureRotations, FramedStream, Interrupt, PacketStream, Transports};
+
+pub const CAPTURE_EXTENSION : &str = "ndjson";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureDirections {
+    //client to server
+    In,
+    //server to client
+    Out
+}
+
+//one line of a capture file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CaptureRecord {
+    pub time: DateTime<Utc>,
+    //unique per connection, since player numbers get reused
+    pub session: String,
+    pub player: usize,
+    pub direction: CaptureDirections,
+    //hex encoded packet, starting with the type (the length prefix/WebSocket framing isn't included)
+    pub data: String
+}
+
+pub fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i+2)?, 16).ok()).collect()
+}
+
+fn open_capture_file(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+//every session that day shares one file
+struct DailyCapture {
+    directory: PathBuf,
+    file: Mutex<Option<(String, File)>>
+}
+impl DailyCapture {
+    fn write(&self, now: &DateTime<Utc>, line: &[u8]) -> Result<(), io::Error> {
+        let day = now.format("%Y-%m-%d").to_string();
+        let mut file = self.file.lock();
+        match &mut *file {
+            Some((open_day, f)) if *open_day == day => f.write_all(line),
+            _ => {
+                let mut f = open_capture_file(&self.directory.join(format!("{day}.{CAPTURE_EXTENSION}")))?;
+                f.write_all(line)?;
+                *file = Some((day, f));
+                Ok(())
+            }
+        }
+    }
+}
+
+enum CaptureOutput {
+    Session(File),
+    Daily(Arc<DailyCapture>)
+}
+
+pub struct Capture {
+    directory: PathBuf,
+    daily: Option<Arc<DailyCapture>>
+}
+impl Capture {
+    pub fn new(config: &CaptureConfig) -> Result<Capture, io::Error> {
+        fs::create_dir_all(&config.directory)?;
+        Ok(Capture {
+            directory: config.directory.clone(),
+            daily: match config.rotation {
+                CaptureRotations::Session => None,
+                CaptureRotations::Daily => Some(Arc::new(DailyCapture { directory: config.directory.clone(), file: Mutex::new(None) })),
+            }
+        })
+    }
+    pub fn start_session(&self, player: usize) -> Result<CaptureSession, io::Error> {
+        let id = format!("{}-p{player}", Utc::now().format("%Y%m%d-%H%M%S%.3f"));
+        let output = match &self.daily {
+            Some(daily) => CaptureOutput::Daily(daily.clone()),
+            None => {
+                let path = self.directory.join(format!("{id}.{CAPTURE_EXTENSION}"));
+                info!(target: NET, player = player; "Recording player {player} to {}", path.display());
+                CaptureOutput::Session(open_capture_file(&path)?)
+            },
+        };
+        Ok(CaptureSession { id, player, output })
+    }
+}
+
+pub struct CaptureSession {
+    id: String,
+    player: usize,
+    output: CaptureOutput
+}
+
+//passes everything through to the inner stream, writing down every packet on the way
+pub struct RecordingStream {
+    inner: Box<dyn FramedStream>,
+    //None once writing has failed, so the log doesn't get spammed
+    session: Option<CaptureSession>
+}
+impl RecordingStream {
+    pub fn new(inner: Box<dyn FramedStream>, session: CaptureSession) -> RecordingStream {
+        RecordingStream { inner, session: Some(session) }
+    }
+    fn write_record(&mut self, direction: CaptureDirections, data: &[u8]) {
+        let Some(session) = &mut self.session else {
+            return
+        };
+        let record = CaptureRecord {
+            time: Utc::now(),
+            session: session.id.clone(),
+            player: session.player,
+            direction,
+            data: encode_hex(data)
+        };
+        let mut line = serde_json::to_string(&record).expect("Capture records should always serialize");
+        line.push('\n');
+        let result = match &mut session.output {
+            CaptureOutput::Session(f) => f.write_all(line.as_bytes()),
+            CaptureOutput::Daily(d) => d.write(&record.time, line.as_bytes()),
+        };
+        if let Err(e) = result {
+            error!(target: NET, player = session.player; "Couldn't write to the capture file, stopping the recording of session {}: {e}", session.id);
+            self.session = None;
+        }
+    }
+}
+impl PacketStream for RecordingStream {
+    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
+        let packet = self.inner.read_packet()?;
+        self.write_record(CaptureDirections::In, &packet);
+        Ok(packet)
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error> {
+        self.write_record(CaptureDirections::Out, &packet);
+        self.inner.write_packet(packet)
+    }
+}
+impl FramedStream for RecordingStream {
+    fn interrupt_handle(&self) -> Result<Box<dyn Interrupt>, io::Error> {
+        self.inner.interrupt_handle()
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.inner.peer_addr()
+    }
+
+    fn transport(&self) -> Transports {
+        self.inner.transport()
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), io::Error> {
+        self.inner.set_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{decode_hex, encode_hex, CaptureDirections, CaptureRecord};
+
+    #[test]
+    fn records_are_hex_ndjson() {
+        let record = CaptureRecord {
+            time: Utc.with_ymd_and_hms(2010, 11, 13, 12, 0, 0).unwrap(),
+            session: "20101113-120000.000-p3".to_owned(),
+            player: 3,
+            direction: CaptureDirections::In,
+            data: encode_hex(b"Prtc\xb0\x01")
+        };
+        assert_eq!(serde_json::to_string(&record).unwrap(),
+            r#"{"time":"2010-11-13T12:00:00Z","session":"20101113-120000.000-p3","player":3,"direction":"in","data":"50727463b001"}"#);
+        assert_eq!(decode_hex(&record.data).unwrap(), b"Prtc\xb0\x01");
+        assert_eq!(decode_hex("5072746"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
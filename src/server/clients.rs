@@ -1,21 +1,22 @@
 use std::collections::HashSet;
-use std::time::Instant;
 use std::{collections::HashMap, time::Duration};
 use std::sync::atomic::Ordering;
 
+use log::{debug, error, info, warn};
 use parking_lot::RwLockWriteGuard;
 use thiserror::Error;
 
-use crate::soaprun::units::{UnitStates, UnitTypes};
-use crate::soaprun::packets::*;
-use crate::soaprun::soaprunners::*;
-use crate::soaprun::position::Position;
-use crate::soaprun::map_attributes::{DRAW_TILES, MAKE_CORPSE_TILES};
+use crate::logging::{ENTITIES, MOVEMENT, NET, ROOMS};
+use soaprun::units::{UnitStates, UnitTypes};
+use soaprun::packets::*;
+use soaprun::soaprunners::*;
+use soaprun::position::Position;
+use soaprun::map_attributes::{DRAW_TILES, MAKE_CORPSE_TILES};
 
 use super::map_attributes::CANVAS_TILES;
-use super::position_extensions::DirectionFlags;
-use super::{FramedStream, MAX_X_COORD, MAX_Y_COORD, MIN_X_COORD, MIN_Y_COORD, PROTOCOL_NAME, PROTOCOL_VERSION};
-use super::{Entity, RoomCoordinates, SoaprunServer};
+use super::position_extensions::{DirectionFlags, RelativeDirection};
+use super::{FramedStream, MAX_X_COORD, MAX_Y_COORD, MIN_X_COORD, MIN_Y_COORD, PROTOCOL_NAME, PROTOCOL_VERSION, REJECTED_PROTOCOL_VERSION};
+use super::{item_names, DeathCauses, Entity, FullServerBehaviors, GameEvents, OutOfPhasePolicies, RecordingStream, RoomCoordinates, SessionPhases, SoaprunServer};
 
 
 #[derive(Error, Debug, Clone, Copy)]
@@ -66,7 +67,13 @@ pub struct Client {
     pub claimed_shield: Option<usize>,
     pub room: HashSet<RoomCoordinates>,
     pub soaprunner: Soaprunner,
-    pub cached_tiles: HashMap<RoomCoordinates,HashMap<Position, u8>>
+    pub cached_tiles: HashMap<RoomCoordinates,HashMap<Position, u8>>,
+    //from the game version in Prtc, decides what gets sent in Flds
+    pub protocol_version: u16,
+    //whatever extensions were turned on with Caps, nothing for the stock client
+    pub capabilities: Capabilities,
+    //Mesgs waiting for the next Flds
+    pub messages: Vec<String>
 }
 impl Client {
     pub fn new(number: usize, color: SoaprunnerColors) -> Client
@@ -86,9 +93,20 @@ impl Client {
                 items: SoaprunnerItems::empty(),
                 movements: vec![CLIENT_SPAWN_POSITION]
             },
-            cached_tiles: HashMap::new()
+            cached_tiles: HashMap::new(),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::empty(),
+            messages: Vec::new()
         }
     }
+    //false if they never asked for messages, since they wouldn't know what to do with one
+    pub fn queue_message(&mut self, message: &str) -> bool {
+        if !self.capabilities.contains(Capabilities::ServerMessages) {
+            return false
+        }
+        self.messages.push(message.to_owned());
+        true
+    }
 
     pub fn verify_nodes(&self, p1 : &Position, p2 : &Position, context: &SoaprunServer) -> Result<usize,MovementValidationErrors> {
         if *p1 == *p2 {
@@ -110,9 +128,11 @@ impl Client {
             DirectionFlags::South => Position::south,
             _ => unreachable!()
         };
-        for i in 0..dist {
-            let prev_pos = dir_f(&p1, i as i16);
-            let curr_pos = dir_f(&p1, 1 + i as i16);
+        //one tile at a time, since the distance doesn't always fit in an i16
+        let mut curr_pos = *p1;
+        for _ in 0..dist {
+            let prev_pos = curr_pos;
+            curr_pos = dir_f(&prev_pos, 1);
             //going outside of these bounds will softlock the client if we validate them
             if curr_pos.x < MIN_X_COORD || MAX_X_COORD < curr_pos.x 
             || curr_pos.y < MIN_Y_COORD || MAX_Y_COORD < curr_pos.y {
@@ -138,8 +158,7 @@ impl Client {
         return Ok(dist);
     }
     pub fn update_position(client: &mut Client, movements: &Vec<Position>, context: &SoaprunServer) -> Result<usize,MovementValidationErrors> {
-        #[cfg(debug_assertions)]
-        println!("{}: {} | {}",
+        debug!(target: MOVEMENT, player = client.number; "Player {} is moving: {} | {}",
             client.number,
             client.soaprunner.movements.iter().map(|p| { p.to_string() }).collect::<Vec<String>>().join(" -> "),
             movements.iter().map(|p| { p.to_string() }).collect::<Vec<String>>().join(" -> ")
@@ -179,13 +198,19 @@ impl Client {
         client.soaprunner.movements = movements.clone();
         Ok(total)
     }
-    pub fn kill(mut client: RwLockWriteGuard<Self>) {
+    pub fn kill(mut client: RwLockWriteGuard<Self>, cause: DeathCauses, entity_index: Option<usize>, context: &SoaprunServer) {
+        if !matches!(client.soaprunner.sprite, SoaprunnerSprites::Dying) {
+            context.log_event(client.number, *client.soaprunner.movements.last().unwrap(), entity_index, GameEvents::Death(cause));
+        }
         client.soaprunner.sprite = SoaprunnerSprites::Dying;
     }
-    pub fn add_kill(mut client: RwLockWriteGuard<Self>, context: &SoaprunServer) {
+    pub fn add_kill(mut client: RwLockWriteGuard<Self>, unit: UnitTypes, entity_index: usize, context: &SoaprunServer) {
         client.kills += 1;
+        let position = *client.soaprunner.movements.last().unwrap();
+        context.log_event(client.number, position, Some(entity_index), GameEvents::Kill { unit, kills: client.kills });
         if client.kills % 10 == 0 {
             client.soaprunner.items.insert(SoaprunnerItems::Crown);
+            context.log_event(client.number, position, None, GameEvents::Crown { kills: client.kills });
             Self::return_sword(client, context);
         }
     }
@@ -200,6 +225,7 @@ impl Client {
                 client.claimed_sword = Some(sword_index);
                 client.soaprunner.items.insert(SoaprunnerItems::Sword);
                 sword.unit.unit_state = UnitStates::Corpse;
+                context.log_event(client.number, *client.soaprunner.movements.last().unwrap(), Some(sword_index), GameEvents::ClaimSword);
             }
         }
     }
@@ -226,6 +252,7 @@ impl Client {
                 context.players_with_shield.fetch_add(1, Ordering::Relaxed);
                 client.soaprunner.items.insert(SoaprunnerItems::Shield);
                 sword.unit.unit_state = UnitStates::Corpse;
+                context.log_event(client.number, *client.soaprunner.movements.last().unwrap(), Some(shield_index), GameEvents::ClaimShield);
             }
         }
     }
@@ -235,6 +262,7 @@ impl Client {
             client.soaprunner.items.remove(SoaprunnerItems::Shield);
             let drop_pos = *client.soaprunner.movements.last().unwrap();
             let claimed_shield = client.claimed_shield.expect("Player had a shield without claiming one!");
+            context.log_event(client.number, drop_pos, Some(claimed_shield), GameEvents::DropShield);
             drop(client);
 
             let mut shield = context.entities.get(claimed_shield)
@@ -267,7 +295,13 @@ impl SoaprunServer {
     {
         let movement_update_result = Client::update_position(&mut client, &movements, self);
         if let Err(e) = movement_update_result {
-            eprintln!("Player {} failed their movement: {} | {}\nReason: {}",
+            //this always ends up disconnecting them
+            self.metrics.movement_error(&e);
+            if !matches!(client.soaprunner.sprite, SoaprunnerSprites::Dying) {
+                let cause = DeathCauses::InvalidMovement { reason: e.to_string() };
+                self.log_event(client.number, *client.soaprunner.movements.last().unwrap(), None, GameEvents::Death(cause));
+            }
+            warn!(target: MOVEMENT, player = client.number; "Player {} failed their movement: {} | {} Reason: {}",
                 client.number,
                 client.soaprunner.movements.iter().map(|p| { p.to_string() }).collect::<Vec<String>>().join(" -> "),
                 movements.iter().map(|p| { p.to_string() }).collect::<Vec<String>>().join(" -> "),
@@ -279,6 +313,9 @@ impl SoaprunServer {
         let color = client.soaprunner.color;
         let items = client.soaprunner.items;
         let num = client.number;
+        let protocol_version = client.protocol_version;
+        let capabilities = client.capabilities;
+        let messages = std::mem::take(&mut client.messages);
         let mut tiles = Vec::new();
         let mut cached_tiles = std::mem::take(&mut client.cached_tiles);
 
@@ -315,7 +352,14 @@ impl SoaprunServer {
             tiles: tiles
         };
 
-        write_packet(stream, packet)?;
+        write_packet(stream, packet.for_protocol_version(protocol_version))?;
+        for message in &messages {
+            let packet = ServerPackets::Message { message };
+            //never send a client anything it didn't ask for
+            if packet.capability().is_none_or(|c| capabilities.contains(c)) {
+                write_packet(stream, packet)?;
+            }
+        }
         Ok(movement_update_result?)
     }
 
@@ -348,16 +392,20 @@ impl SoaprunServer {
         }
         match colliding_r.unit.unit_type {
             UnitTypes::Goal => {
+                if !matches!(client.soaprunner.sprite, SoaprunnerSprites::Winning) {
+                    let event = GameEvents::Win { items: item_names(client.soaprunner.items), kills: client.kills };
+                    self.log_event(client.number, *client.soaprunner.movements.last().unwrap(), Some(entity_index as usize), event);
+                }
                 client.soaprunner.sprite = SoaprunnerSprites::Winning
             },
             UnitTypes::Closer => {
                 if matches!(colliding_r.unit.unit_state, UnitStates::Active) {
                     drop(colliding_r);
                     if client.soaprunner.items.contains(SoaprunnerItems::Sword) {
-                        Client::add_kill(client, self);
+                        Client::add_kill(client, UnitTypes::Closer, entity_index as usize, self);
                         Entity::kill(colliding.write(),Duration::from_secs(5), self);
                     } else {
-                        Client::kill(client);
+                        Client::kill(client, DeathCauses::Unit { unit: UnitTypes::Closer }, Some(entity_index as usize), self);
                         Entity::add_kill(colliding.write());
                     }
                 }
@@ -368,35 +416,36 @@ impl SoaprunServer {
             },
             UnitTypes::Wuss => {
                 if matches!(colliding_r.unit.unit_state, UnitStates::Active) {
-                    Client::add_kill(client, self);
+                    Client::add_kill(client, UnitTypes::Wuss, entity_index as usize, self);
                     drop(colliding_r);
                     Entity::kill(colliding.write(),Duration::from_secs(5), self);
                 }
             },
             UnitTypes::Crawl => {
                 if client.soaprunner.items.contains(SoaprunnerItems::Sword) {
-                    Client::add_kill(client, self);
+                    Client::add_kill(client, UnitTypes::Crawl, entity_index as usize, self);
                     drop(colliding_r);
                     Entity::kill(colliding.write(),Duration::from_secs(10), self);
                 }
                 else {
-                    Client::kill(client);
+                    Client::kill(client, DeathCauses::Unit { unit: UnitTypes::Crawl }, Some(entity_index as usize), self);
                 }
             },
             UnitTypes::Hummer | UnitTypes::Rounder |
             UnitTypes::Gate | UnitTypes::Cross => {
                 if !client.soaprunner.items.contains(SoaprunnerItems::Shield) {
-                    Client::kill(client);
+                    let unit = colliding_r.unit.unit_type;
+                    Client::kill(client, DeathCauses::Unit { unit }, Some(entity_index as usize), self);
                 }
             },
             UnitTypes::Chase => {
                 if matches!(colliding_r.unit.unit_state, UnitStates::Active) {
                     if client.soaprunner.items.contains(SoaprunnerItems::Sword) {
-                        Client::add_kill(client, self);
+                        Client::add_kill(client, UnitTypes::Chase, entity_index as usize, self);
                         drop(colliding_r);
                         Entity::kill(colliding.write(), Duration::from_secs(5), self);
                     } else {
-                        Client::kill(client);
+                        Client::kill(client, DeathCauses::Unit { unit: UnitTypes::Chase }, Some(entity_index as usize), self);
                     }
                 }
             },
@@ -411,209 +460,318 @@ impl SoaprunServer {
     }
     pub fn client_handler(&self, mut stream: Box<dyn FramedStream>, idle_timeout: u64)
     {
-        let stream = stream.as_mut();
+        let peer = match stream.peer_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => "unknown".to_owned(),
+        };
         let (num, client) = match self.borrow_player() {
             Ok(n) => n,
-            Err(_) => return,
+            Err(_) => {
+                let rejections = self.metrics.connection_full(stream.transport());
+                warn!(target: NET, peer = peer.as_str(); "Server is full, turning {peer} away ({rejections} so far)");
+                let _ = match self.full_server_behavior {
+                    FullServerBehaviors::Over => write_packet(stream.as_mut(), ServerPackets::Over),
+                    FullServerBehaviors::WelcomeAndClose => write_packet(stream.as_mut(), ServerPackets::Welcome),
+                };
+                return
+            },
         };
 
-        println!("Welcome player {num}!");
-        if let Ok(_) = write_packet(stream, ServerPackets::Welcome)
+        if let Some(capture) = &self.capture {
+            match capture.start_session(num) {
+                Ok(session) => stream = Box::new(RecordingStream::new(stream, session)),
+                Err(e) => error!(target: NET, player = num, peer = peer.as_str(); "Couldn't start recording player {num}, continuing without it: {e}"),
+            }
+        }
+        let stream = stream.as_mut();
+
+        match stream.interrupt_handle() {
+            Ok(interrupt) => {
+                self.interrupts.lock().insert(num, interrupt);
+            },
+            Err(e) => {
+                error!(target: NET, player = num, peer = peer.as_str(); "Couldn't get an interrupt handle for player {num}: {e}");
+                let _ = self.return_player(client, num);
+                return
+            }
+        }
+
+        self.metrics.connection_accepted(stream.transport());
+        info!(target: NET, player = num, peer = peer.as_str(); "Welcome player {num} from {peer}!");
+        let spawn = *client.read().soaprunner.movements.last().unwrap();
+        self.log_event(num, spawn, None, GameEvents::Join { peer: peer.clone() });
+        let mut phase = SessionPhases::Welcome;
+        //we may have missed the shutdown, so we need to check after registering
+        if !self.is_shutting_down() && write_packet(stream, ServerPackets::Welcome).is_ok()
         {
+            //the client has its WLCM, so it should be asking for the protocol next
+            phase = SessionPhases::Protocol;
             let dur = Duration::from_secs(idle_timeout);
-            let mut idle_timer = Instant::now();
+            let mut idle_timer = self.clock.now();
             loop {
-                if self.idle_timeout != 0 && idle_timer.elapsed() >= dur {
-                    eprintln!("Player {num} has idled for too long!");
+                if self.idle_timeout != 0 && self.clock.now().saturating_duration_since(idle_timer) >= dur {
+                    info!(target: NET, player = num, peer = peer.as_str(); "Player {num} has idled for too long!");
                     break;
                 }
-                match read_packet(stream)
+                let packet = match read_packet(stream)
                 {
-                    Ok(packet) => match packet
-                    {
-                        ClientPackets::ProtocolRequest { game_version } => {
-                            println!("Player {num} is requesting the server Protocol from game version {game_version}");
-                            if let Err(_) = write_packet(stream, ServerPackets::Protocol {
-                                protocol: *PROTOCOL_NAME,
-                                version: PROTOCOL_VERSION
-                            }) {
-                                break
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        //being interrupted means someone else removed us, so the error is expected
+                        if !self.interrupts.lock().contains_key(&num) {
+                            info!(target: NET, player = num, peer = peer.as_str(); "Disconnecting player {num}");
+                            let _ = write_packet(stream, ServerPackets::Void);
+                        }
+                        else {
+                            self.metrics.read_error(&e);
+                            warn!(target: NET, player = num, peer = peer.as_str(); "Error reading from player {num}: {e}");
+                        }
+                        break;
+                    }
+                };
+                let packet_type = packet.packet_type();
+                self.metrics.packet_received(packet_type);
+                //packets from after the client's version are just as unexpected as ones from the wrong phase
+                match phase.next(&packet).filter(|_| packet.protocol_version() <= client.read().protocol_version) {
+                    Some(next) => {
+                        //the handshake is over, so they get as long as anyone else in the game
+                        if matches!(next, SessionPhases::InGame) && !matches!(phase, SessionPhases::InGame) {
+                            let connection_timeout = match self.connection_timeout {
+                                0 => None,
+                                t => Some(Duration::from_secs(t)),
+                            };
+                            if let Err(e) = stream.set_timeout(connection_timeout) {
+                                warn!(target: NET, player = num, peer = peer.as_str(); "Couldn't set the connection timeout for player {num}: {e}");
                             }
-                        },
-                        ClientPackets::ConnectionTest { data } => {
-                            println!("Player {num} is testing their connection...");
-                            if let Err(_) = write_packet(stream, ServerPackets::ConnectionTest { data: data }) {
+                        }
+                        phase = next
+                    },
+                    None => {
+                        self.metrics.out_of_phase(packet_type);
+                        match self.out_of_phase_policy {
+                            OutOfPhasePolicies::Ignore => continue,
+                            OutOfPhasePolicies::Log => {
+                                warn!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} sent {packet_type} during {phase:?}, ignoring it");
+                                continue
+                            },
+                            OutOfPhasePolicies::Disconnect => {
+                                warn!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} sent {packet_type} during {phase:?}, disconnecting them");
                                 break
+                            },
+                        }
+                    },
+                }
+                match packet
+                {
+                    ClientPackets::ProtocolRequest { game_version } => {
+                        info!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} is requesting the server Protocol from game version {game_version}");
+                        let accepted = self.accepted_game_versions.as_ref().is_none_or(|v| v.contains(&game_version));
+                        self.metrics.game_version(game_version, accepted);
+                        if !accepted {
+                            warn!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "...but game version {game_version} isn't accepted here");
+                            let _ = write_packet(stream, ServerPackets::Protocol {
+                                protocol: *PROTOCOL_NAME,
+                                version: REJECTED_PROTOCOL_VERSION
+                            });
+                            break
+                        }
+                        //older clients only carry on if they get back the protocol version they expect
+                        //their layouts are unverified, so they only get it if accepted_game_versions lets them in by name
+                        //everyone else (including anything we don't know about) is treated like the latest version
+                        let opted_in = self.accepted_game_versions.as_ref().is_some_and(|v| v.contains(&game_version));
+                        let protocol_version = protocol_version_for(game_version).filter(|_| opted_in).unwrap_or(PROTOCOL_VERSION);
+                        client.write().protocol_version = protocol_version;
+                        if let Err(_) = write_packet(stream, ServerPackets::Protocol {
+                            protocol: *PROTOCOL_NAME,
+                            version: protocol_version
+                        }) {
+                            break
+                        }
+                    },
+                    ClientPackets::Capabilities { capabilities } => {
+                        let enabled = capabilities & self.capabilities;
+                        info!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} supports {capabilities:?}, turning on {enabled:?}");
+                        client.write().capabilities = enabled;
+                        if write_packet(stream, ServerPackets::Capabilities { capabilities: enabled }).is_err() {
+                            break
+                        }
+                    },
+                    ClientPackets::ConnectionTest { data } => {
+                        debug!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} is testing their connection...");
+                        if let Err(_) = write_packet(stream, ServerPackets::ConnectionTest { data: data }) {
+                            break
+                        }
+                    },
+                    ClientPackets::LogDebugMessage { message } => {
+                        info!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Debug message from player {num}: {message}");
+                        if let Err(_) = write_packet(stream, ServerPackets::Void) {
+                            break
+                        }
+                    },
+                    ClientPackets::MapAttributeRequest => {
+                        debug!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} wants the map attributes");
+                        if let Err(_) = write_packet(stream, ServerPackets::MapAttributesResponse {
+                            map_attributes: &self.map_attributes
+                        } ) {
+                            break
+                        }
+                    },
+                    ClientPackets::RoomRequest { coords } => {
+                        debug!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} wants the room at {coords}");
+                        if let Some(room) = self.rooms.get(&coords) {
+                            let mut cw = client.write();
+                            if let Some(cache) = cw.cached_tiles.get_mut(&coords) {
+                                cache.clear();
                             }
-                        },
-                        ClientPackets::LogDebugMessage { message } => {
-                            println!("Debug message from player {num}: {message}");
-                            if let Err(_) = write_packet(stream, ServerPackets::Void) {
+                            drop(cw);
+                            let r = room.read();
+                            if let Err(_) = write_packet(stream, ServerPackets::RoomResponse {
+                                coords: coords,
+                                room: &r
+                            }) {
                                 break
                             }
-                        },
-                        ClientPackets::MapAttributeRequest => {
-                            println!("Player {num} wants the map attributes");
-                            if let Err(_) = write_packet(stream, ServerPackets::MapAttributesResponse {
-                                map_attributes: &self.map_attributes
-                            } ) {
+                        }
+                        else {
+                            if let Err(_) = write_packet(stream, ServerPackets::RoomResponse {
+                                coords: coords,
+                                room: &self.default_room
+                            }) {
                                 break
                             }
-                        },
-                        ClientPackets::RoomRequest { coords } => {
-                            println!("Player {num} wants the room at {coords}");
-                            if let Some(room) = self.rooms.get(&coords) {
-                                let mut cw = client.write();
-                                if let Some(cache) = cw.cached_tiles.get_mut(&coords) {
-                                    cache.clear();
-                                }
-                                drop(cw);
-                                let r = room.read();
-                                if let Err(_) = write_packet(stream, ServerPackets::RoomResponse {
-                                    coords: coords,
-                                    room: &r
-                                }) {
-                                    break
-                                }
-                            }
-                            else {
-                                if let Err(_) = write_packet(stream, ServerPackets::RoomResponse {
-                                    coords: coords,
-                                    room: &self.default_room
-                                }) {
-                                    break
-                                }
+                        }
+                    },
+                    ClientPackets::ChangeColor { color, movements } => {
+                        debug!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} wants to change color to {color}");
+                        let mut cw = client.write();
+                        if matches!(cw.soaprunner.sprite, SoaprunnerSprites::Walking) { //idle players can't change color
+                            cw.soaprunner.color = match color {
+                                0 => SoaprunnerColors::Green,
+                                1 => SoaprunnerColors::Pink,
+                                2 => SoaprunnerColors::Blue,
+                                3 => SoaprunnerColors::Yellow,
+                                _ => cw.soaprunner.color
+                            };
+                            idle_timer = self.clock.now(); //any valid change color request means they're not idle
+                            match self.update_client_and_send_fields(stream, cw, movements) {
+                                Ok(_) => { },
+                                Err(_) => break,
                             }
-                        },
-                        ClientPackets::ChangeColor { color, movements } => {
-                            println!("Player {num} wants to change color to {color}");
-                            let mut cw = client.write();
-                            if matches!(cw.soaprunner.sprite, SoaprunnerSprites::Walking) { //idle players can't change color
-                                cw.soaprunner.color = match color {
-                                    0 => SoaprunnerColors::Green,
-                                    1 => SoaprunnerColors::Pink,
-                                    2 => SoaprunnerColors::Blue,
-                                    3 => SoaprunnerColors::Yellow,
-                                    _ => cw.soaprunner.color
-                                };
-                                idle_timer = Instant::now(); //any valid change color request means they're not idle
-                                match self.update_client_and_send_fields(stream, cw, movements) {
-                                    Ok(_) => { },
-                                    Err(_) => break,
+                        }
+                        else {
+                            warn!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} can't change color while {:?}!", cw.soaprunner.sprite);
+                            break
+                        }
+                    },
+                    ClientPackets::MyPosition { movements } => {
+                        match self.update_client_and_send_fields(stream, client.write(), movements) {
+                            Ok(t) => {
+                                if t > 0 {
+                                    idle_timer = self.clock.now()
                                 }
+                            },
+                            Err(_) => break,
+                        }
+                    },
+                    ClientPackets::DrawOnField { position, tile, movements } => {
+                        debug!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} wants to change {position} to tile {tile}");
+                        let state = client.read().soaprunner.sprite;
+                        if matches!(state, SoaprunnerSprites::Walking) { //idle players can't draw
+                            let tiles = self.try_draw_on_field(&position, tile);
+                            self.log_event(num, position, None, GameEvents::Draw { tile, tiles });
+                            idle_timer = self.clock.now(); //any valid draw request means the player is still alive
+                            match self.update_client_and_send_fields(stream,  client.write(), movements) {
+                                Ok(_) => { },
+                                Err(_) => break,
                             }
-                            else {
-                                eprintln!("...but player {num} can't change color while {:?}!", cw.soaprunner.sprite);
-                                break
-                            }
-                        },
-                        ClientPackets::MyPosition { movements } => {
+                        }
+                        else {
+                            warn!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} can't draw while {:?}!", state);
+                            break
+                        }
+                    },
+                    ClientPackets::HitNonPlayerUnit { index, movements } => {
+                        debug!(target: ENTITIES, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} has collided with entity {index}");
+                        let state = client.read().soaprunner.sprite;
+                        if matches!(state, SoaprunnerSprites::Idle | SoaprunnerSprites::Walking) {
+                            //aquiring two locks is a little annoying
+                            //but we NEED to have control over when the write lock ends during collision to avoid deadlocks with entities
+                            self.handle_collision(client.write(), index);
+                            //downside: a truly AFK player could have their timer reset while standing on top of an item spawn point
+                            //upside: a waiting player won't be screwed over right after they pick up an item they've been waiting for
+                            idle_timer = self.clock.now();
                             match self.update_client_and_send_fields(stream, client.write(), movements) {
-                                Ok(t) => {
-                                    if t > 0 {
-                                        idle_timer = Instant::now()
-                                    }
-                                },
+                                Ok(_) => { },
                                 Err(_) => break,
                             }
-                        },
-                        ClientPackets::DrawOnField { position, tile, movements } => {
-                            println!("Player {num} wants to change {position} to tile {tile}");
-                            let state = client.read().soaprunner.sprite;
-                            if matches!(state, SoaprunnerSprites::Walking) { //idle players can't draw
-                                let _ = self.try_draw_on_field(&position, tile);
-                                idle_timer = Instant::now(); //any valid draw request means the player is still alive
-                                match self.update_client_and_send_fields(stream,  client.write(), movements) {
-                                    Ok(_) => { },
-                                    Err(_) => break,
+                        }
+                        else {
+                            warn!(target: ENTITIES, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} can't collide while {:?}!", state);
+                            break
+                        }
+                    },
+                    ClientPackets::MakeCorpse { position } => {
+                        info!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} wants to become a corpse at {position}");
+                        let mut cw = client.write();
+                        match (cw.has_made_corpse, matches!(cw.soaprunner.sprite, SoaprunnerSprites::Dying)) {
+                            (false, true) => {
+                                cw.has_made_corpse = true;
+                                drop(cw);
+                                //try_spawn_corpse needs write access to every client
+                                let tiles = self.try_spawn_corpse(&position);
+                                self.log_event(num, position, None, GameEvents::Corpse { tiles });
+                                if let Err(_) = write_packet(stream, ServerPackets::Void) {
+                                    break
                                 }
-                            }
-                            else {
-                                eprintln!("...but player {num} can't draw while {:?}!", state);
+                            },
+                            (false, false) => {
+                                warn!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} isn't dead yet, they're {:?}!", cw.soaprunner.sprite);
                                 break
-                            }
-                        },
-                        ClientPackets::HitNonPlayerUnit { index, movements } => {
-                            println!("Player {num} has collided with entity {index}");
-                            let state = client.read().soaprunner.sprite;
-                            if matches!(state, SoaprunnerSprites::Idle | SoaprunnerSprites::Walking) {
-                                //aquiring two locks is a little annoying
-                                //but we NEED to have control over when the write lock ends during collision to avoid deadlocks with entities
-                                self.handle_collision(client.write(), index);
-                                //downside: a truly AFK player could have their timer reset while standing on top of an item spawn point
-                                //upside: a waiting player won't be screwed over right after they pick up an item they've been waiting for
-                                idle_timer = Instant::now();
-                                match self.update_client_and_send_fields(stream, client.write(), movements) {
-                                    Ok(_) => { },
-                                    Err(_) => break,
-                                }
-                            }
-                            else {
-                                eprintln!("...but player {num} can't collide while {:?}!", state);
+                            },
+                            (true, false) => {
+                                warn!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} already made a corpse!");
                                 break
-                            }
-                        },
-                        ClientPackets::MakeCorpse { position } => {
-                            println!("Player {num} wants to become a corpse at {position}");
+                            },
+                            (true, true) => {
+                                warn!(target: ROOMS, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} already made a corpse, and they're {:?}...?!", cw.soaprunner.sprite);
+                                break
+                            },
+                        }
+                    },
+                    ClientPackets::Bye => {
+                        info!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Goodbye player {num}!");
+                        let _ = write_packet(stream, ServerPackets::Void);
+                        break
+                    },
+                    ClientPackets::Heaven { movements } => {
+                        info!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "Player {num} would like to enter heaven");
+                        let state = client.read().soaprunner.sprite;
+                        if matches!(state, SoaprunnerSprites::Idle | SoaprunnerSprites::Walking) {
+                            idle_timer = self.clock.now(); //give the player a chance to see what happened
+                            Client::return_sword(client.write(), self);
+                            Client::drop_shield(client.write(), self);
                             let mut cw = client.write();
-                            match (cw.has_made_corpse, matches!(cw.soaprunner.sprite, SoaprunnerSprites::Dying)) {
-                                (false, true) => {
-                                    cw.has_made_corpse = true;
-                                    drop(cw);
-                                    //try_spawn_corpse needs write access to every client
-                                    let _ = self.try_spawn_corpse(&position);
-                                    if let Err(_) = write_packet(stream, ServerPackets::Void) {
-                                        break
-                                    }
-                                },
-                                (false, false) => {
-                                    eprintln!("...but player {num} isn't dead yet, they're {:?}!", cw.soaprunner.sprite);
-                                    break
-                                },
-                                (true, false) => {
-                                    eprintln!("...but player {num} already made a corpse!");
-                                    break
-                                },
-                                (true, true) => {
-                                    eprintln!("...but player {num} already made a corpse, and they're {:?}...?!", cw.soaprunner.sprite);
-                                    break
-                                },
+                            cw.soaprunner.sprite = SoaprunnerSprites::Ghost;
+                            self.log_event(num, *cw.soaprunner.movements.last().unwrap(), None, GameEvents::Heaven);
+                            match self.update_client_and_send_fields(stream, cw, movements) {
+                                Ok(_) => { },
+                                Err(_) => break,
                             }
-                        },
-                        ClientPackets::Bye => {
-                            println!("Goodbye player {num}!");
-                            let _ = write_packet(stream, ServerPackets::Void);
+                        }
+                        else {
+                            warn!(target: NET, player = num, peer = peer.as_str(), packet = packet_type; "...but player {num} can't enter heaven while {:?}!", state);
                             break
-                        },
-                        ClientPackets::Heaven { movements } => {
-                            println!("Player {num} would like to enter heaven");
-                            let state = client.read().soaprunner.sprite;
-                            if matches!(state, SoaprunnerSprites::Idle | SoaprunnerSprites::Walking) {
-                                idle_timer = Instant::now(); //give the player a chance to see what happened
-                                Client::return_sword(client.write(), self);
-                                Client::drop_shield(client.write(), self);
-                                let mut cw = client.write();
-                                cw.soaprunner.sprite = SoaprunnerSprites::Ghost;
-                                match self.update_client_and_send_fields(stream, cw, movements) {
-                                    Ok(_) => { },
-                                    Err(_) => break,
-                                }
-                            }
-                            else {
-                                eprintln!("...but player {num} can't enter heaven while {:?}!", state);
-                                break
-                            }
-                        },
+                        }
                     },
-                    Err(e) => {
-                        eprintln!("Error: {e}");
-                        break;
-                    }
                 }
             }
         }
+        debug!(target: NET, player = num, peer = peer.as_str(); "Player {num} left during {phase:?}");
+        self.interrupts.lock().remove(&num);
         Client::return_sword(client.write(), self);
         Client::drop_shield(client.write(), self);
+        let last_position = *client.read().soaprunner.movements.last().unwrap();
+        self.log_event(num, last_position, None, GameEvents::Leave);
         let _ = self.return_player(client, num);
     }
 }
\ No newline at end of file
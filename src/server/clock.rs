@@ -0,0 +1,38 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+//where the server gets the time from, so simulations can run without waiting on the wall clock
+pub trait Clock : Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+//only moves when something sleeps on it, and sleeping never blocks
+pub struct ManualClock {
+    now: Mutex<Instant>
+}
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock { now: Mutex::new(Instant::now()) }
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+    fn sleep(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
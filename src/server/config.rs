@@ -4,8 +4,10 @@ use std::path::{Path, PathBuf};
 use serde::{self, Deserialize, Serialize};
 use thiserror::{self, Error};
 
-use crate::soaprun::position::Position;
-use crate::soaprun::units::UnitTypes;
+use crate::logging::LogConfig;
+use soaprun::packets::Capabilities;
+use soaprun::position::Position;
+use soaprun::units::UnitTypes;
 use super::{Entity, EntityProperties, RoomVerificationBounds, RoomVerificationModes};
 
 #[derive(serde::Deserialize, Debug)]
@@ -18,11 +20,126 @@ pub struct ServerConfig
     pub attributes_path: PathBuf,
     pub connection_timeout: u64,
     pub idle_timeout: u64,
+    //timeout (in seconds) for new connections until they've finished the handshake and are in the game
+    //0 uses connection_timeout for the whole connection
+    #[serde(default)]
+    pub handshake_timeout: u64,
+    //how many connections one address can have open at once (including ones still in the handshake), 0 is unlimited
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+    //how many new connections one address can open per second, 0 is unlimited
+    #[serde(default)]
+    pub max_new_connections_per_ip: usize,
     pub max_players: u32,
+    //what to do with clients that connect while max_players are already playing
+    #[serde(default)]
+    pub full_server_behavior: FullServerBehaviors,
+    //what to do with packets that come at the wrong point in the handshake (like myPo before Prtc)
+    #[serde(default)]
+    pub out_of_phase_policy: OutOfPhasePolicies,
+    //game versions (from Prtc, 432 for v0.432) that are allowed to play, anyone can play if this isn't set
+    //older versions listed here get the protocol version they expect, everyone else gets PROTOCOL_VERSION
+    pub accepted_game_versions: Option<Vec<u16>>,
+    //extensions that clients can turn on with Caps, the stock client never asks for any
+    #[serde(default)]
+    pub extensions: Vec<Extensions>,
     pub max_player_movement_nodes_per_packet: u32,
     pub max_player_distance_per_movement_node: u32,
     pub max_player_distance_per_packet: u32,
-    pub address: String
+    pub address: String,
+    pub dispatch: Option<DispatchConfig>,
+    //while this file exists, the server is closed and refuses new players
+    //each line of the file is a comment shown by the dispatch (or the dispatch comments if it's empty)
+    pub maintenance_path: Option<PathBuf>,
+    //where drawings and corpses are saved, so they stay around after a restart
+    pub map_save_path: Option<PathBuf>,
+    //gameplay events (joins, deaths, kills, items, etc.) are appended here as one JSON object per line
+    pub event_log_path: Option<PathBuf>,
+    //how often (in seconds) the map is saved while the server is running, 0 only saves it on shutdown
+    #[serde(default)]
+    pub autosave_interval: u64,
+    //where to listen for admin console connections, only connections from this machine are accepted
+    pub admin_address: Option<String>,
+    //where to serve Prometheus metrics (at /metrics), anyone who can reach this can read them
+    pub metrics_address: Option<String>,
+    //records every packet sent and received, so problems can be reproduced later
+    pub capture: Option<CaptureConfig>,
+    //JSON lists of addresses/CIDR ranges, see access.rs for the format
+    //anyone matching the ban list is disconnected immediately
+    pub ban_list_path: Option<PathBuf>,
+    //if set, only addresses on this list can connect (unless they're also banned)
+    pub allow_list_path: Option<PathBuf>,
+    //seeds entity movement and player colors, so the same inputs always give the same game
+    pub rng_seed: Option<u64>,
+    #[serde(default)]
+    pub logging: LogConfig
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy)]
+pub enum FullServerBehaviors {
+    //what Pixel's server did, but the final client ignores it and waits for a WLCM forever
+    Over,
+    //the client gets in, then immediately sees the connection drop
+    #[default]
+    WelcomeAndClose
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy)]
+pub enum OutOfPhasePolicies {
+    //drop the packet without a response
+    Ignore,
+    //same as Ignore, but warn about it
+    Log,
+    //the stock client never does this, so whoever's on the other end can go
+    #[default]
+    Disconnect
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub enum Extensions {
+    //Mesg, for the admin console's say command
+    ServerMessages
+}
+impl Extensions {
+    pub fn capability(self) -> Capabilities {
+        match self {
+            Extensions::ServerMessages => Capabilities::ServerMessages,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy)]
+pub enum CaptureRotations {
+    //a new file for every connection
+    #[default]
+    Session,
+    //one file per day (UTC) shared by everyone
+    Daily
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CaptureConfig
+{
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub rotation: CaptureRotations
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct DispatchConfig
+{
+    //where to listen for the client's server.cgi request
+    pub address: String,
+    //where the client should connect to for the game server
+    //this is sent to the client as-is, so it needs to be reachable from the outside
+    pub public_ip: String,
+    pub public_port: u16,
+    //the client only prints these when the server isn't open, and only reads up to six of them
+    //these are used when the maintenance file doesn't have any comments of its own
+    #[serde(default)]
+    pub comments: Vec<String>,
+    //older clients also check the protocol version here, so a server for them has to say theirs instead of the latest
+    pub protocol_version: Option<u16>
 }
 
 
@@ -0,0 +1,124 @@
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use log::{error, info, warn};
+
+use crate::logging::NET;
+use super::http::{read_http_request, write_http_response};
+use super::{DispatchConfig, ServerStatus, SoaprunServer, PROTOCOL_NAME, PROTOCOL_VERSION};
+
+pub const DISPATCH_STATUS_OPEN : &str = "open";
+//anything other than "open" makes the client print the comments instead of connecting
+pub const DISPATCH_STATUS_CLOSED : &str = "closed";
+pub const DISPATCH_MAX_COMMENTS : usize = 6;
+//comments that are exactly this are shown as a blank line by the client
+pub const DISPATCH_BLANK_COMMENT : &str = "<br>";
+
+//the client doesn't understand HTTP, it just runs a regex over whatever it receives
+//so the only things that matter are the "<html" line, the "Pixel" line, and the tab separated status line after it
+pub fn make_dispatch_body(status: &str, ip: &str, port: u16, version: u16, comments: &[String]) -> String
+{
+    let protocol = std::str::from_utf8(PROTOCOL_NAME).unwrap().trim_end_matches('\0');
+    //the first field is ignored by the client, but Pixel's server put a timestamp there
+    let mut fields = vec![
+        chrono::Utc::now().format("%y%m%d-%H%M%S").to_string(),
+        sanitize_field(status),
+        sanitize_field(ip),
+        port.to_string(),
+        protocol.to_owned(),
+        version.to_string()
+    ];
+    fields.extend(comments.iter().take(DISPATCH_MAX_COMMENTS).map(|c| {
+        match sanitize_field(c) {
+            c if c.is_empty() => DISPATCH_BLANK_COMMENT.to_owned(),
+            c => c
+        }
+    }));
+    format!("<html><body>\nPixel<br>\n{}\n</body></html>\n", fields.join("\t"))
+}
+
+//tabs and newlines would break the client's regex, and empty fields won't match it at all
+fn sanitize_field(field: &str) -> String
+{
+    field.replace(['\t', '\r', '\n'], " ").trim().to_owned()
+}
+
+impl SoaprunServer
+{
+    pub fn start_dispatch(&'static self, config: &'static DispatchConfig) -> Result<(), std::io::Error>
+    {
+        let listener = TcpListener::bind(&config.address)?;
+        info!(target: NET, "Dispatch listening on {}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for acc_res in listener.incoming() {
+                match acc_res
+                {
+                    Ok(stream) => {
+                        thread::spawn(move || {
+                            if let Err(e) = self.dispatch_handler(stream, config) {
+                                warn!(target: NET, "Error answering dispatch request: {:?}", e);
+                            }
+                        });
+                    },
+                    Err(e) => {
+                        error!(target: NET, "Error accepting incoming dispatch connection: {:?}", e)
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+    fn dispatch_handler(&self, mut stream: TcpStream, config: &DispatchConfig) -> Result<(), std::io::Error>
+    {
+        //the client always asks for server.cgi, but there's nothing else here anyways
+        let _ = read_http_request(&mut stream)?;
+
+        let body = match self.get_status() {
+            ServerStatus::Open => make_dispatch_body(DISPATCH_STATUS_OPEN, &config.public_ip, config.public_port, config.protocol_version.unwrap_or(PROTOCOL_VERSION), &config.comments),
+            ServerStatus::Closed(comments) => make_dispatch_body(DISPATCH_STATUS_CLOSED, &config.public_ip, config.public_port, config.protocol_version.unwrap_or(PROTOCOL_VERSION), &comments),
+        };
+        let peer_addr = stream.peer_addr()?;
+        write_http_response(&mut stream, "200 OK", "text/html", &body)?;
+        info!(target: NET, peer:% = peer_addr; "Answered dispatch request from {peer_addr}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::{make_dispatch_body, DISPATCH_STATUS_CLOSED, DISPATCH_STATUS_OPEN, PROTOCOL_VERSION};
+
+    //straight from docs/protocol.md
+    const CLIENT_REGEX : &str = r".*(<html).*(?:\r\n|\r|\n)(Pixel).*(?:\r\n|\r|\n).*\t+(.+)\t+(.+)\t+(\d+)\t+(Soaprun)\t+(\d+)(?:\t+(.+)){0,6}";
+
+    #[test]
+    fn client_regex_matches() {
+        let re = Regex::new(CLIENT_REGEX).unwrap();
+
+        let body = make_dispatch_body(DISPATCH_STATUS_OPEN, "127.0.0.1", 1002, PROTOCOL_VERSION, &[]);
+        let caps = re.captures(&body).unwrap();
+        assert_eq!(&caps[3], "open");
+        assert_eq!(&caps[4], "127.0.0.1");
+        assert_eq!(&caps[5], "1002");
+        assert_eq!(&caps[6], "Soaprun");
+        assert_eq!(&caps[7], "64");
+
+        let comments = ["Down for maintenance".to_owned(), "".to_owned(), "back\tsoon".to_owned()];
+        let body = make_dispatch_body(DISPATCH_STATUS_CLOSED, "127.0.0.1", 1002, PROTOCOL_VERSION, &comments);
+        let caps = re.captures(&body).unwrap();
+        assert_eq!(&caps[3], "closed");
+        assert_eq!(&caps[7], "64");
+        let line = body.lines().nth(2).unwrap();
+        assert!(line.ends_with("\tDown for maintenance\t<br>\tback soon"));
+    }
+
+    #[test]
+    fn comments_are_limited() {
+        let comments = Vec::from_iter((0..10).map(|i| i.to_string()));
+        let body = make_dispatch_body(DISPATCH_STATUS_OPEN, "127.0.0.1", 1002, PROTOCOL_VERSION, &comments);
+        let line = body.lines().nth(2).unwrap();
+        assert_eq!(line.split('\t').count(), 6 + 6);
+    }
+}
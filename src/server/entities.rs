@@ -1,15 +1,15 @@
-use std::{collections::HashSet, thread::sleep, time::Duration};
+use std::{collections::HashSet, time::{Duration, Instant}};
 use std::sync::atomic::Ordering;
 
 use parking_lot::{RwLock, RwLockWriteGuard};
-use rand::{seq::SliceRandom, thread_rng};
+use rand::seq::SliceRandom;
 
-use crate::soaprun::position::Position;
-use crate::soaprun::units::{Unit, UnitStates, UnitTypes};
-use crate::soaprun::soaprunners::{SoaprunnerSprites, SoaprunnerItems};
+use soaprun::position::Position;
+use soaprun::units::{Unit, UnitStates, UnitTypes};
+use soaprun::soaprunners::{SoaprunnerSprites, SoaprunnerItems};
 
 use super::map_attributes::REMOVE_CORPSE_TILES;
-use super::position_extensions::DirectionFlags;
+use super::position_extensions::{DirectionFlags, RelativeDirection};
 use super::SoaprunServer;
 
 pub struct KillCounter {
@@ -28,6 +28,8 @@ pub enum EntityProperties {
 }
 pub struct Entity {
     pub spawn_position: Position,
+    pub spawn_type: UnitTypes,
+    pub spawn_direction: u8,
     pub counter: usize,
     pub properties: EntityProperties,
     pub unit: Unit,
@@ -36,30 +38,48 @@ impl Entity {
     pub fn new(pos: Position, unit_type: UnitTypes, direction: u8, properties: EntityProperties) -> Entity {
         return Entity {
             spawn_position: pos,
+            spawn_type: unit_type,
+            spawn_direction: direction,
             counter: 0,
             properties: properties,
             unit: Unit {
                 teleport_trigger: 0,
-                unit_state: match unit_type {
-                    UnitTypes::Goal => UnitStates::Active,
-                    UnitTypes::Closer => UnitStates::Sleeping,
-                    UnitTypes::Sword => UnitStates::Active,
-                    UnitTypes::Crawl => UnitStates::Active, //There is no footage of a Crawl respawning, so I assume they never slept
-                    UnitTypes::Hummer => UnitStates::Active,
-                    UnitTypes::Rounder => UnitStates::Active,
-                    UnitTypes::Wuss => UnitStates::Sleeping,
-                    UnitTypes::Chase => UnitStates::Sleeping,
-                    UnitTypes::Gate => UnitStates::Active,
-                    UnitTypes::Shield => UnitStates::Active,
-                    UnitTypes::Cross => UnitStates::Active,
-                    UnitTypes::Snail => UnitStates::Sleeping,
-                },
+                unit_state: Self::get_spawn_state(unit_type),
                 unit_type: unit_type,
                 direction,
                 movements: vec![pos],
             },
         }
     }
+    fn get_spawn_state(unit_type: UnitTypes) -> UnitStates {
+        match unit_type {
+            UnitTypes::Goal => UnitStates::Active,
+            UnitTypes::Closer => UnitStates::Sleeping,
+            UnitTypes::Sword => UnitStates::Active,
+            UnitTypes::Crawl => UnitStates::Active, //There is no footage of a Crawl respawning, so I assume they never slept
+            UnitTypes::Hummer => UnitStates::Active,
+            UnitTypes::Rounder => UnitStates::Active,
+            UnitTypes::Wuss => UnitStates::Sleeping,
+            UnitTypes::Chase => UnitStates::Sleeping,
+            UnitTypes::Gate => UnitStates::Active,
+            UnitTypes::Shield => UnitStates::Active,
+            UnitTypes::Cross => UnitStates::Active,
+            UnitTypes::Snail => UnitStates::Sleeping,
+        }
+    }
+    //puts the entity back the way it was when the server started
+    //anyone holding this entity needs to let go of it first!
+    pub fn reset(&mut self) {
+        self.unit.unit_type = self.spawn_type;
+        self.unit.unit_state = Self::get_spawn_state(self.spawn_type);
+        self.unit.direction = self.spawn_direction;
+        self.unit.movements = vec![self.spawn_position];
+        self.unit.teleport_trigger = self.unit.teleport_trigger.wrapping_add(1);
+        self.counter = 0;
+        if let EntityProperties::KillCounter(kc) = &mut self.properties {
+            kc.kills = 0;
+        }
+    }
     pub fn kill(mut unit: RwLockWriteGuard<Self>, dead_len: Duration, context: &SoaprunServer) {
         unit.unit.unit_state = UnitStates::Corpse;
         unit.counter = context.get_entity_delay(dead_len);
@@ -329,229 +349,243 @@ impl SoaprunServer {
     }
     
     pub fn entity_handler(&self) {
-        loop {
-            for entity in &self.entities {
-                let entity_r = entity.read();
-                //anything with => { } doesn't move/need to be updated here
-                match entity_r.unit.unit_type {
-                    //these don't do anything, so...
-                    UnitTypes::Goal | UnitTypes::Sword | UnitTypes::Shield |
-                    UnitTypes::Hummer | UnitTypes::Rounder => { },
-                    //these are basically the same enemy, so shared case it is
-                    UnitTypes::Closer | UnitTypes::Wuss => {
-                        match entity_r.unit.unit_state {
-                            UnitStates::Sleeping | UnitStates::Active => {
-                                drop(entity_r);
-                                if let Some(entity_w) = Entity::wait(entity.write()) {
-                                    let curr_pos = *entity_w.unit.movements.last().unwrap();
-                                    drop(entity_w);
-                                    let options = self.get_closer_movement_options(entity);
-                                    
-                                    let mut entity_w = entity.write();
-                                    match options {
-                                        Some(opts) => {
-                                            if let Some(new_pos) = opts.choose(&mut thread_rng()) {
-                                                entity_w.unit.movements = vec![curr_pos, *new_pos];
-                                            } else {
-                                                entity_w.unit.movements = vec![curr_pos];
-                                            }
-                                            entity_w.unit.unit_state = UnitStates::Active;
-                                        },
-                                        None => {
-                                            entity_w.unit.unit_state = UnitStates::Sleeping;
+        while !self.is_shutting_down() {
+            self.entity_tick();
+            self.clock.sleep(self.entity_update_rate);
+        }
+    }
+    //moves every entity one update along
+    pub fn entity_tick(&self) {
+        let tick_start = Instant::now();
+        for entity in &self.entities {
+            let entity_r = entity.read();
+            //anything with => { } doesn't move/need to be updated here
+            match entity_r.unit.unit_type {
+                //these don't do anything, so...
+                UnitTypes::Goal | UnitTypes::Sword | UnitTypes::Shield |
+                UnitTypes::Hummer | UnitTypes::Rounder => { },
+                //these are basically the same enemy, so shared case it is
+                UnitTypes::Closer | UnitTypes::Wuss => {
+                    match entity_r.unit.unit_state {
+                        UnitStates::Sleeping | UnitStates::Active => {
+                            drop(entity_r);
+                            if let Some(entity_w) = Entity::wait(entity.write()) {
+                                let curr_pos = *entity_w.unit.movements.last().unwrap();
+                                drop(entity_w);
+                                let options = self.get_closer_movement_options(entity);
+                                
+                                let mut entity_w = entity.write();
+                                match options {
+                                    Some(opts) => {
+                                        if let Some(new_pos) = opts.choose(&mut *self.rng.lock()) {
+                                            entity_w.unit.movements = vec![curr_pos, *new_pos];
+                                        } else {
                                             entity_w.unit.movements = vec![curr_pos];
                                         }
+                                        entity_w.unit.unit_state = UnitStates::Active;
+                                    },
+                                    None => {
+                                        entity_w.unit.unit_state = UnitStates::Sleeping;
+                                        entity_w.unit.movements = vec![curr_pos];
                                     }
-                                    entity_w.counter = self.get_entity_delay(Duration::from_millis(500));
                                 }
-                            },
-                            UnitStates::Corpse => {
-                                drop(entity_r);
-                                if let Some(mut entity_w) = Entity::wait(entity.write()) {
-                                    entity_w.unit.unit_state = UnitStates::Sleeping;
-                                    entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
-                                    entity_w.unit.movements = vec![entity_w.spawn_position];
+                                entity_w.counter = self.get_entity_delay(Duration::from_millis(500));
+                            }
+                        },
+                        UnitStates::Corpse => {
+                            drop(entity_r);
+                            if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                                entity_w.unit.unit_state = UnitStates::Sleeping;
+                                entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
+                                entity_w.unit.movements = vec![entity_w.spawn_position];
+                                entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
+                            }
+                        },
+                        UnitStates::Flickering => {},
+                        UnitStates::Gone => {},
+                    }
+                },
+                UnitTypes::Crawl => {
+                    match entity_r.unit.unit_state {
+                        UnitStates::Sleeping => {
+                            //I have yet to see evidence of a sleeping Crawl, so this is a failsafe
+                            drop(entity_r);
+                            entity.write().unit.unit_state = UnitStates::Active
+                        },
+                        UnitStates::Active => {
+                            let last_pos = *entity_r.unit.movements.last().unwrap();
+                            let spawn_pos = entity_r.spawn_position;
+                            drop(entity_r);
+
+                            if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                                if last_pos != spawn_pos {
+                                    entity_w.unit.movements = vec![last_pos, spawn_pos];
                                     entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
                                 }
-                            },
-                            UnitStates::Flickering => {},
-                            UnitStates::Gone => {},
-                        }
-                    },
-                    UnitTypes::Crawl => {
-                        match entity_r.unit.unit_state {
-                            UnitStates::Sleeping => {
-                                //I have yet to see evidence of a sleeping Crawl, so this is a failsafe
-                                drop(entity_r);
-                                entity.write().unit.unit_state = UnitStates::Active
-                            },
-                            UnitStates::Active => {
-                                let last_pos = *entity_r.unit.movements.last().unwrap();
-                                let spawn_pos = entity_r.spawn_position;
-                                drop(entity_r);
-
-                                if let Some(mut entity_w) = Entity::wait(entity.write()) {
-                                    if last_pos != spawn_pos {
-                                        entity_w.unit.movements = vec![last_pos, spawn_pos];
+                                else {
+                                    //don't hold a write lock while checking the players
+                                    drop(entity_w);
+                                    let targets = self.get_crawl_attack_locations(last_pos);
+                                    if let Some(attack_pos) = targets.choose(&mut *self.rng.lock()) {
+                                        let mut entity_w = entity.write();
+                                        entity_w.unit.movements = vec![last_pos, *attack_pos];
                                         entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
                                     }
-                                    else {
-                                        //don't hold a write lock while checking the players
-                                        drop(entity_w);
-                                        let targets = self.get_crawl_attack_locations(last_pos);
-                                        if let Some(attack_pos) = targets.choose(&mut rand::thread_rng()) {
-                                            let mut entity_w = entity.write();
-                                            entity_w.unit.movements = vec![last_pos, *attack_pos];
-                                            entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
-                                        }
-                                    }
                                 }
-                            },
-                            UnitStates::Corpse => {
-                                drop(entity_r);
-                                if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                            }
+                        },
+                        UnitStates::Corpse => {
+                            drop(entity_r);
+                            if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                                entity_w.unit.unit_state = UnitStates::Active;
+                                entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
+                                entity_w.unit.movements = vec![entity_w.spawn_position];
+                                entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
+                            }
+                        },
+                        UnitStates::Flickering => { },
+                        UnitStates::Gone => { },
+                    };
+                },
+                UnitTypes::Chase => {
+                    match entity_r.unit.unit_state {
+                        UnitStates::Sleeping => {
+                            drop(entity_r);
+                            if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                                if self.players_with_shield.load(Ordering::Relaxed) > 0 {
                                     entity_w.unit.unit_state = UnitStates::Active;
-                                    entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
-                                    entity_w.unit.movements = vec![entity_w.spawn_position];
-                                    entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
                                 }
-                            },
-                            UnitStates::Flickering => { },
-                            UnitStates::Gone => { },
-                        };
-                    },
-                    UnitTypes::Chase => {
-                        match entity_r.unit.unit_state {
-                            UnitStates::Sleeping => {
+                            }
+                        },
+                        UnitStates::Active => {
+                            if self.players_with_shield.load(Ordering::Relaxed) > 0 {
+                                let pos = *entity_r.unit.movements.last().unwrap();
                                 drop(entity_r);
-                                if let Some(mut entity_w) = Entity::wait(entity.write()) {
-                                    if self.players_with_shield.load(Ordering::Relaxed) > 0 {
-                                        entity_w.unit.unit_state = UnitStates::Active;
-                                    }
-                                }
-                            },
-                            UnitStates::Active => {
-                                if self.players_with_shield.load(Ordering::Relaxed) > 0 {
-                                    let pos = *entity_r.unit.movements.last().unwrap();
-                                    drop(entity_r);
-                                    let options = self.get_chase_movement_options(pos);
-                                    let mut entity_w = entity.write();
-                                    if let Some(opt) = options.choose(&mut thread_rng()) {
-                                        entity_w.unit.movements = vec![pos, *opt];
-                                    } else {
-                                        entity_w.unit.movements = vec![pos];
-                                    }
+                                let options = self.get_chase_movement_options(pos);
+                                let mut entity_w = entity.write();
+                                if let Some(opt) = options.choose(&mut *self.rng.lock()) {
+                                    entity_w.unit.movements = vec![pos, *opt];
                                 } else {
-                                    drop(entity_r);
-                                    let mut entity_w = entity.write();
-                                    entity_w.unit.unit_state = UnitStates::Sleeping;
-                                    entity_w.unit.movements = vec![*entity_w.unit.movements.last().unwrap()]
+                                    entity_w.unit.movements = vec![pos];
                                 }
-                            },
-                            UnitStates::Corpse => {
+                            } else {
                                 drop(entity_r);
-                                if let Some(mut entity_w) = Entity::wait(entity.write()) {
-                                    entity_w.unit.unit_state = UnitStates::Sleeping;
-                                    entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
-                                    entity_w.counter = self.get_entity_delay(Duration::from_secs(5));
-                                    entity_w.unit.movements = vec![entity_w.spawn_position];
-                                }
-                            },
-                            UnitStates::Flickering => { },
-                            UnitStates::Gone => { },
-                        }
-                    },
-                    UnitTypes::Gate => {
-                        let mut set: HashSet<Position> = HashSet::from_iter(match &entity_r.properties {
-                            EntityProperties::SwitchedDirection(sd) => sd.switches.clone(),
-                            _ => unreachable!()
-                        });
-                        drop(entity_r);
-
-                        if let Some(entity_w) = Entity::wait(entity.write()) {
-                            drop(entity_w);
-
-                            for (_, p) in self.players.read().iter() {
-                                if set.is_empty() {
-                                    break
-                                }
-                                let pr = p.read();
-                                if matches!(pr.soaprunner.sprite, SoaprunnerSprites::Idle | SoaprunnerSprites::Walking) {
-                                    let pp = pr.soaprunner.movements.last().unwrap();
-                                    set.remove(pp);
-                                }
+                                let mut entity_w = entity.write();
+                                entity_w.unit.unit_state = UnitStates::Sleeping;
+                                entity_w.unit.movements = vec![*entity_w.unit.movements.last().unwrap()]
+                            }
+                        },
+                        UnitStates::Corpse => {
+                            drop(entity_r);
+                            if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                                entity_w.unit.unit_state = UnitStates::Sleeping;
+                                entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
+                                entity_w.counter = self.get_entity_delay(Duration::from_secs(5));
+                                entity_w.unit.movements = vec![entity_w.spawn_position];
                             }
+                        },
+                        UnitStates::Flickering => { },
+                        UnitStates::Gone => { },
+                    }
+                },
+                UnitTypes::Gate => {
+                    let mut set: HashSet<Position> = HashSet::from_iter(match &entity_r.properties {
+                        EntityProperties::SwitchedDirection(sd) => sd.switches.clone(),
+                        _ => unreachable!()
+                    });
+                    drop(entity_r);
 
-                            let mut entity_w = entity.write();
-                            let prop = match &entity_w.properties {
-                                EntityProperties::SwitchedDirection(sd) => sd,
-                                _ => unreachable!()
-                            };
+                    if let Some(entity_w) = Entity::wait(entity.write()) {
+                        drop(entity_w);
+
+                        for (_, p) in self.players.read().iter() {
                             if set.is_empty() {
-                                entity_w.unit.direction = prop.on_dir;
-                                entity_w.counter = self.get_entity_delay(Duration::from_secs(5))
-                            } else {
-                                entity_w.unit.direction = prop.off_dir;
+                                break
+                            }
+                            let pr = p.read();
+                            if matches!(pr.soaprunner.sprite, SoaprunnerSprites::Idle | SoaprunnerSprites::Walking) {
+                                let pp = pr.soaprunner.movements.last().unwrap();
+                                set.remove(pp);
                             }
                         }
-                    },
-                    UnitTypes::Cross => {
-                        drop(entity_r);
-                        if let Some(mut entity_w) = Entity::wait(entity.write()) {
-                            entity_w.unit.direction = entity_w.unit.direction.wrapping_add(1) % 4;
-                            entity_w.counter = self.get_entity_delay(Duration::from_secs(10));
+
+                        let mut entity_w = entity.write();
+                        let prop = match &entity_w.properties {
+                            EntityProperties::SwitchedDirection(sd) => sd,
+                            _ => unreachable!()
+                        };
+                        if set.is_empty() {
+                            entity_w.unit.direction = prop.on_dir;
+                            entity_w.counter = self.get_entity_delay(Duration::from_secs(5))
+                        } else {
+                            entity_w.unit.direction = prop.off_dir;
                         }
-                    },
-                    UnitTypes::Snail => {
-                        match entity_r.unit.unit_state {
-                            UnitStates::Sleeping | UnitStates::Active => {
-                                let pos = *entity_r.unit.movements.last().unwrap();
-                                let radius = match entity_r.unit.unit_state {
-                                    UnitStates::Sleeping => 1,
-                                    UnitStates::Active => 2,
-                                    _ => unreachable!()
-                                };
-                                let _ = self.try_update_tile(&pos, &*REMOVE_CORPSE_TILES, |t| { t - 16 });
-                                drop(entity_r);
+                    }
+                },
+                UnitTypes::Cross => {
+                    drop(entity_r);
+                    if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                        entity_w.unit.direction = entity_w.unit.direction.wrapping_add(1) % 4;
+                        entity_w.counter = self.get_entity_delay(Duration::from_secs(10));
+                    }
+                },
+                UnitTypes::Snail => {
+                    match entity_r.unit.unit_state {
+                        UnitStates::Sleeping | UnitStates::Active => {
+                            let pos = *entity_r.unit.movements.last().unwrap();
+                            let radius = match entity_r.unit.unit_state {
+                                UnitStates::Sleeping => 1,
+                                UnitStates::Active => 2,
+                                _ => unreachable!()
+                            };
+                            let _ = self.try_update_tile(&pos, &*REMOVE_CORPSE_TILES, |t| { t - 16 });
+                            drop(entity_r);
 
-                                if let Some(entity_w) = Entity::wait(entity.write()) {
-                                    drop(entity_w);
+                            if let Some(entity_w) = Entity::wait(entity.write()) {
+                                drop(entity_w);
 
-                                    let options = self.get_snail_movement_options(pos, radius);
-                                    let mut entity_w = entity.write();
-                                    match options {
-                                        Some(o) => {
-                                            entity_w.unit.unit_state = UnitStates::Active;
-                                            if let Some(new_pos) = o.choose(&mut thread_rng()) {
-                                                entity_w.unit.movements = vec![pos, *new_pos];
-                                            }
-                                            entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
-                                        },
-                                        None => {
-                                            entity_w.unit.unit_state = UnitStates::Sleeping;
-                                            if entity_w.unit.movements.len() > 1 {
-                                                entity_w.unit.movements = vec![pos];
-                                            }
-                                        },
-                                    }
-                                }
-                            },
-                            UnitStates::Corpse => {
-                                drop(entity_r);
-                                if let Some(mut entity_w) = Entity::wait(entity.write()) {
-                                    entity_w.unit.unit_state = UnitStates::Sleeping;
-                                    entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
-                                    entity_w.unit.movements = vec![entity_w.spawn_position];
-                                    entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
+                                let options = self.get_snail_movement_options(pos, radius);
+                                let mut entity_w = entity.write();
+                                match options {
+                                    Some(o) => {
+                                        entity_w.unit.unit_state = UnitStates::Active;
+                                        if let Some(new_pos) = o.choose(&mut *self.rng.lock()) {
+                                            entity_w.unit.movements = vec![pos, *new_pos];
+                                        }
+                                        entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
+                                    },
+                                    None => {
+                                        entity_w.unit.unit_state = UnitStates::Sleeping;
+                                        if entity_w.unit.movements.len() > 1 {
+                                            entity_w.unit.movements = vec![pos];
+                                        }
+                                    },
                                 }
-                            },
-                            UnitStates::Flickering => { },
-                            UnitStates::Gone => { },
-                        }
-                    },
-                }
+                            }
+                        },
+                        UnitStates::Corpse => {
+                            drop(entity_r);
+                            if let Some(mut entity_w) = Entity::wait(entity.write()) {
+                                entity_w.unit.unit_state = UnitStates::Sleeping;
+                                entity_w.unit.teleport_trigger = entity_w.unit.teleport_trigger.wrapping_add(1);
+                                entity_w.unit.movements = vec![entity_w.spawn_position];
+                                entity_w.counter = self.get_entity_delay(Duration::from_secs(1));
+                            }
+                        },
+                        UnitStates::Flickering => { },
+                        UnitStates::Gone => { },
+                    }
+                },
             }
-            sleep(self.entity_update_rate);
+        }
+        self.metrics.entity_tick(tick_start.elapsed());
+    }
+    //runs updates until the server's clock reaches the time, sleeping on the clock between them
+    //meant for a ManualClock, where this doesn't actually wait
+    pub fn simulate_until(&self, time: Instant) {
+        while self.clock.now() + self.entity_update_rate <= time {
+            self.entity_tick();
+            self.clock.sleep(self.entity_update_rate);
         }
     }
 }
\ No newline at end of file
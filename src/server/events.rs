@@ -0,0 +1,107 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::Serialize;
+
+use crate::logging::SERVER;
+use soaprun::position::Position;
+use soaprun::soaprunners::SoaprunnerItems;
+use soaprun::units::UnitTypes;
+
+use super::SoaprunServer;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "cause")]
+pub enum DeathCauses {
+    //the entity index is in the event itself
+    Unit { unit: UnitTypes },
+    //the server didn't believe the player's movement, so it killed them instead
+    InvalidMovement { reason: String }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event")]
+pub enum GameEvents {
+    Join { peer: String },
+    Leave,
+    Death(DeathCauses),
+    Kill { unit: UnitTypes, kills: usize },
+    Crown { kills: usize },
+    ClaimSword,
+    ClaimShield,
+    DropShield,
+    Win { items: Vec<&'static str>, kills: usize },
+    Heaven,
+    //for corpses and drawings, the position is the tile that was changed, not where the player is
+    Corpse { tiles: usize },
+    Draw { tile: u8, tiles: usize }
+}
+
+#[derive(Serialize)]
+struct EventRecord<'a> {
+    time: DateTime<Utc>,
+    player: usize,
+    position: Position,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity: Option<usize>,
+    #[serde(flatten)]
+    event: &'a GameEvents
+}
+
+pub fn item_names(items: SoaprunnerItems) -> Vec<&'static str> {
+    Vec::from_iter(items.iter_names().map(|(n, _)| n))
+}
+
+pub fn open_event_log(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl SoaprunServer
+{
+    pub fn log_event(&self, player: usize, position: Position, entity: Option<usize>, event: GameEvents)
+    {
+        let Some(event_log) = &self.event_log else {
+            return
+        };
+        let record = EventRecord {
+            time: Utc::now(),
+            player,
+            position,
+            entity,
+            event: &event
+        };
+        let mut line = serde_json::to_string(&record).expect("Events should always serialize");
+        line.push('\n');
+        //one write per line, so a crash can't leave half an event in the middle of the file
+        if let Err(e) = event_log.lock().write_all(line.as_bytes()) {
+            error!(target: SERVER, "Couldn't write to the event log, {:?} was lost: {e}", event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use soaprun::position::Position;
+    use soaprun::units::UnitTypes;
+
+    use super::{DeathCauses, EventRecord, GameEvents};
+
+    #[test]
+    fn events_are_flat_json() {
+        let event = GameEvents::Death(DeathCauses::Unit { unit: UnitTypes::Closer });
+        let record = EventRecord {
+            time: Utc.with_ymd_and_hms(2010, 11, 13, 12, 0, 0).unwrap(),
+            player: 3,
+            position: Position { x: 10, y: -2 },
+            entity: Some(7),
+            event: &event
+        };
+        assert_eq!(serde_json::to_string(&record).unwrap(),
+            r#"{"time":"2010-11-13T12:00:00Z","player":3,"position":{"x":10,"y":-2},"entity":7,"event":"Death","cause":"Unit","unit":"Closer"}"#);
+    }
+}
@@ -0,0 +1,38 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+//just enough HTTP for the dispatch and metrics endpoints, neither of which need anything fancy
+
+const HTTP_TIMEOUT : Duration = Duration::from_secs(5);
+const HTTP_MAX_REQUEST_LENGTH : usize = 1024;
+
+//returns the requested path, or an empty string if the request line didn't have one
+pub fn read_http_request(stream: &mut TcpStream) -> Result<String, io::Error>
+{
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    //we should let the client finish sending the request before answering, even if we don't need all of it
+    let mut request = Vec::new();
+    let mut buf = [0u8; 256];
+    while request.len() < HTTP_MAX_REQUEST_LENGTH && !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+            Err(_) => break,
+        }
+    }
+    //"GET /path HTTP/1.1"
+    let request = String::from_utf8_lossy(&request);
+    Ok(request.split_whitespace().nth(1).unwrap_or_default().to_owned())
+}
+
+pub fn write_http_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<(), io::Error>
+{
+    let response = format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
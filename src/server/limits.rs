@@ -0,0 +1,113 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::SoaprunServer;
+
+const RATE_WINDOW : Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct IpConnections {
+    open: usize,
+    //when each connection in the last RATE_WINDOW was accepted, oldest first
+    recent: VecDeque<Instant>
+}
+
+#[derive(Default)]
+pub struct ConnectionTracker {
+    ips: HashMap<IpAddr, IpConnections>
+}
+impl ConnectionTracker {
+    //0 means no limit for either of them
+    fn try_open(&mut self, ip: IpAddr, max_open: usize, max_per_second: usize, now: Instant) -> Result<(), LimitExceeded>
+    {
+        //forget about anyone who's done connecting, so this doesn't grow forever
+        self.ips.retain(|_, c| {
+            while c.recent.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+                c.recent.pop_front();
+            }
+            c.open > 0 || !c.recent.is_empty()
+        });
+
+        let connections = self.ips.entry(ip).or_default();
+        if max_open > 0 && connections.open >= max_open {
+            return Err(LimitExceeded::TooManyConnections(connections.open));
+        }
+        if max_per_second > 0 && connections.recent.len() >= max_per_second {
+            return Err(LimitExceeded::TooManyNewConnections(connections.recent.len()));
+        }
+        connections.open += 1;
+        connections.recent.push_back(now);
+        Ok(())
+    }
+    fn close(&mut self, ip: &IpAddr)
+    {
+        if let Some(connections) = self.ips.get_mut(ip) {
+            connections.open -= 1;
+        }
+    }
+}
+
+pub enum LimitExceeded {
+    TooManyConnections(usize),
+    TooManyNewConnections(usize)
+}
+impl std::fmt::Display for LimitExceeded
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::TooManyConnections(n) => write!(f, "already has {n} open connections"),
+            LimitExceeded::TooManyNewConnections(n) => write!(f, "opened {n} connections in the last second"),
+        }
+    }
+}
+
+//keeps the connection counted until it's dropped, so it should live as long as the client thread
+pub struct ConnectionGuard {
+    server: &'static SoaprunServer,
+    ip: IpAddr
+}
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.server.connections.lock().close(&self.ip);
+    }
+}
+
+impl SoaprunServer
+{
+    pub fn open_connection(&'static self, ip: IpAddr) -> Result<ConnectionGuard, LimitExceeded>
+    {
+        let ip = ip.to_canonical();
+        self.connections.lock().try_open(ip, self.max_connections_per_ip, self.max_new_connections_per_ip, Instant::now())?;
+        Ok(ConnectionGuard { server: self, ip })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use super::ConnectionTracker;
+
+    #[test]
+    fn limits_are_per_ip() {
+        let mut tracker = ConnectionTracker::default();
+        let a: IpAddr = "203.0.113.1".parse().unwrap();
+        let b: IpAddr = "203.0.113.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(tracker.try_open(a, 2, 0, start).is_ok());
+        assert!(tracker.try_open(a, 2, 0, start).is_ok());
+        assert!(tracker.try_open(a, 2, 0, start).is_err());
+        assert!(tracker.try_open(b, 2, 0, start).is_ok());
+        tracker.close(&a);
+        assert!(tracker.try_open(a, 2, 0, start).is_ok());
+
+        let mut tracker = ConnectionTracker::default();
+        assert!(tracker.try_open(a, 0, 2, start).is_ok());
+        assert!(tracker.try_open(a, 0, 2, start).is_ok());
+        assert!(tracker.try_open(a, 0, 2, start + Duration::from_millis(500)).is_err());
+        assert!(tracker.try_open(a, 0, 2, start + Duration::from_millis(1000)).is_ok());
+    }
+}
@@ -0,0 +1,41 @@
+use std::fs;
+use std::io::ErrorKind;
+
+use log::error;
+
+use crate::logging::SERVER;
+use super::SoaprunServer;
+
+pub enum ServerStatus {
+    Open,
+    //the comments are shown to clients through the dispatch, empty lines are shown as blank lines
+    Closed(Vec<String>)
+}
+
+impl SoaprunServer
+{
+    //the server is closed for as long as the maintenance file exists, so it can be toggled without a restart
+    pub fn get_status(&self) -> ServerStatus
+    {
+        let path = match &self.maintenance_path {
+            Some(p) => p,
+            None => return ServerStatus::Open,
+        };
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let comments = Vec::from_iter(contents.lines().map(|l| l.to_owned()));
+                //an empty file still closes the server, it just doesn't have anything to say about it
+                if comments.is_empty() {
+                    ServerStatus::Closed(self.dispatch.as_ref().map(|d| d.comments.clone()).unwrap_or_default())
+                } else {
+                    ServerStatus::Closed(comments)
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => ServerStatus::Open,
+            Err(e) => {
+                error!(target: SERVER, "Couldn't read the maintenance file, assuming the server is closed: {e}");
+                ServerStatus::Closed(Vec::new())
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use parking_lot::Mutex;
+
+use crate::logging::NET;
+use soaprun::packets::{protocol_version_for, ReadPacketErrors};
+use super::http::{read_http_request, write_http_response};
+use super::{MovementValidationErrors, SoaprunServer, Transports};
+
+//why new connections never made it to the probe
+#[derive(Debug, Clone, Copy)]
+pub enum RefusalReasons {
+    Access,
+    Limit,
+    Closed
+}
+impl RefusalReasons {
+    const ALL : [RefusalReasons; 3] = [RefusalReasons::Access, RefusalReasons::Limit, RefusalReasons::Closed];
+    fn label(&self) -> &'static str {
+        match self {
+            RefusalReasons::Access => "access",
+            RefusalReasons::Limit => "limit",
+            RefusalReasons::Closed => "closed",
+        }
+    }
+}
+
+fn read_error_label(error: &ReadPacketErrors) -> &'static str {
+    match error {
+        ReadPacketErrors::InvalidLengthError { .. } => "InvalidLengthError",
+        ReadPacketErrors::InvalidTypeError { .. } => "InvalidTypeError",
+        ReadPacketErrors::IOError(_) => "IOError",
+        ReadPacketErrors::UnexpectedDataAmount { .. } => "UnexpectedDataAmount",
+        ReadPacketErrors::InvalidDataError { .. } => "InvalidDataError",
+    }
+}
+fn movement_error_label(error: &MovementValidationErrors) -> &'static str {
+    match error {
+        MovementValidationErrors::MisalignedNodesError => "MisalignedNodesError",
+        MovementValidationErrors::NodesTooFarError { .. } => "NodesTooFarError",
+        MovementValidationErrors::OutOfBoundsError => "OutOfBoundsError",
+        MovementValidationErrors::MoveAlongEdgeError => "MoveAlongEdgeError",
+        MovementValidationErrors::InvalidTileTypeError { .. } => "InvalidTileTypeError",
+        MovementValidationErrors::TooManyNodesError { .. } => "TooManyNodesError",
+        MovementValidationErrors::TotalTooFarError { .. } => "TotalTooFarError",
+        MovementValidationErrors::FirstMovementError => "FirstMovementError",
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    peak_players: AtomicUsize,
+    //indexed by Transports
+    accepted: [AtomicU64; 2],
+    full: [AtomicU64; 2],
+    failed: [AtomicU64; 2],
+    //indexed by RefusalReasons
+    refused: [AtomicU64; 3],
+    //these are only touched once per packet/disconnect, so a lock is fine
+    packets: Mutex<BTreeMap<&'static str, u64>>,
+    read_errors: Mutex<BTreeMap<&'static str, u64>>,
+    movement_errors: Mutex<BTreeMap<&'static str, u64>>,
+    out_of_phase: Mutex<BTreeMap<&'static str, u64>>,
+    //accepted and rejected connections for each game version
+    //None is every version no release of the client ever had, since anyone can send any number
+    game_versions: Mutex<BTreeMap<Option<u16>, [u64; 2]>>,
+    entity_ticks: AtomicU64,
+    entity_tick_nanos: AtomicU64,
+    entity_tick_max_nanos: AtomicU64
+}
+impl Metrics {
+    pub fn player_joined(&self, players: usize) {
+        self.peak_players.fetch_max(players, Ordering::Relaxed);
+    }
+    pub fn connection_accepted(&self, transport: Transports) {
+        self.accepted[transport as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    //returns how many have been rejected so far
+    pub fn connection_full(&self, transport: Transports) -> u64 {
+        self.full[transport as usize].fetch_add(1, Ordering::Relaxed);
+        self.total_full()
+    }
+    pub fn total_full(&self) -> u64 {
+        self.full.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+    pub fn connection_failed(&self, transport: Transports) {
+        self.failed[transport as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn connection_refused(&self, reason: RefusalReasons) {
+        self.refused[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn packet_received(&self, packet_type: &'static str) {
+        *self.packets.lock().entry(packet_type).or_default() += 1;
+    }
+    pub fn read_error(&self, error: &ReadPacketErrors) {
+        *self.read_errors.lock().entry(read_error_label(error)).or_default() += 1;
+    }
+    pub fn movement_error(&self, error: &MovementValidationErrors) {
+        *self.movement_errors.lock().entry(movement_error_label(error)).or_default() += 1;
+    }
+    pub fn out_of_phase(&self, packet_type: &'static str) {
+        *self.out_of_phase.lock().entry(packet_type).or_default() += 1;
+    }
+    pub fn game_version(&self, game_version: u16, accepted: bool) {
+        let version = protocol_version_for(game_version).map(|_| game_version);
+        self.game_versions.lock().entry(version).or_default()[!accepted as usize] += 1;
+    }
+    pub fn entity_tick(&self, duration: Duration) {
+        let nanos = duration.as_nanos() as u64;
+        self.entity_ticks.fetch_add(1, Ordering::Relaxed);
+        self.entity_tick_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.entity_tick_max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+}
+fn write_labelled(out: &mut String, name: &str, label: &str, values: &BTreeMap<&'static str, u64>) {
+    for (value, count) in values {
+        let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+    }
+}
+
+impl SoaprunServer
+{
+    //Prometheus text format
+    pub fn render_metrics(&self) -> String
+    {
+        let m = &self.metrics;
+        let mut out = String::new();
+        let nanos_to_secs = |n: u64| n as f64 / 1e9;
+
+        write_header(&mut out, "soapdispenser_players", "gauge", "Players currently connected.");
+        let _ = writeln!(out, "soapdispenser_players {}", self.players.read().len());
+        write_header(&mut out, "soapdispenser_players_peak", "gauge", "Most players connected at once since startup.");
+        let _ = writeln!(out, "soapdispenser_players_peak {}", m.peak_players.load(Ordering::Relaxed));
+        write_header(&mut out, "soapdispenser_players_with_shield", "gauge", "Players currently holding a shield.");
+        let _ = writeln!(out, "soapdispenser_players_with_shield {}", self.players_with_shield.load(Ordering::Relaxed));
+
+        write_header(&mut out, "soapdispenser_connections_total", "counter", "Connections that made it through the probe, by outcome and transport.");
+        for (outcome, counters) in [("accepted", &m.accepted), ("full", &m.full), ("failed", &m.failed)] {
+            for t in Transports::ALL {
+                let _ = writeln!(out, "soapdispenser_connections_total{{outcome=\"{outcome}\",transport=\"{}\"}} {}",
+                    t.label(), counters[t as usize].load(Ordering::Relaxed));
+            }
+        }
+        write_header(&mut out, "soapdispenser_connections_refused_total", "counter", "Connections closed before the probe, by reason.");
+        for r in RefusalReasons::ALL {
+            let _ = writeln!(out, "soapdispenser_connections_refused_total{{reason=\"{}\"}} {}",
+                r.label(), m.refused[r as usize].load(Ordering::Relaxed));
+        }
+
+        write_header(&mut out, "soapdispenser_packets_received_total", "counter", "Packets received from clients, by type.");
+        write_labelled(&mut out, "soapdispenser_packets_received_total", "type", &m.packets.lock());
+        write_header(&mut out, "soapdispenser_read_error_disconnects_total", "counter", "Players disconnected because a packet couldn't be read, by error.");
+        write_labelled(&mut out, "soapdispenser_read_error_disconnects_total", "error", &m.read_errors.lock());
+        write_header(&mut out, "soapdispenser_movement_error_disconnects_total", "counter", "Players disconnected for invalid movement, by error.");
+        write_labelled(&mut out, "soapdispenser_movement_error_disconnects_total", "error", &m.movement_errors.lock());
+        write_header(&mut out, "soapdispenser_out_of_phase_packets_total", "counter", "Packets received at the wrong point in the handshake, by type.");
+        write_labelled(&mut out, "soapdispenser_out_of_phase_packets_total", "type", &m.out_of_phase.lock());
+        write_header(&mut out, "soapdispenser_game_versions_total", "counter", "Protocol requests, by the client's game version (other if no release of the client had it) and whether it was accepted.");
+        for (version, counts) in m.game_versions.lock().iter() {
+            let version = version.map_or("other".to_owned(), |v| v.to_string());
+            for (outcome, count) in ["accepted", "rejected"].iter().zip(counts) {
+                let _ = writeln!(out, "soapdispenser_game_versions_total{{version=\"{version}\",outcome=\"{outcome}\"}} {count}");
+            }
+        }
+
+        write_header(&mut out, "soapdispenser_entity_tick_seconds", "summary", "Time spent updating every entity once.");
+        let _ = writeln!(out, "soapdispenser_entity_tick_seconds_sum {}", nanos_to_secs(m.entity_tick_nanos.load(Ordering::Relaxed)));
+        let _ = writeln!(out, "soapdispenser_entity_tick_seconds_count {}", m.entity_ticks.load(Ordering::Relaxed));
+        write_header(&mut out, "soapdispenser_entity_tick_seconds_max", "gauge", "Longest entity update since startup.");
+        let _ = writeln!(out, "soapdispenser_entity_tick_seconds_max {}", nanos_to_secs(m.entity_tick_max_nanos.load(Ordering::Relaxed)));
+        out
+    }
+    pub fn start_metrics(&'static self, address: &str) -> Result<(), std::io::Error>
+    {
+        let listener = TcpListener::bind(address)?;
+        info!(target: NET, "Metrics listening on {}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for acc_res in listener.incoming() {
+                match acc_res
+                {
+                    Ok(stream) => {
+                        thread::spawn(move || {
+                            if let Err(e) = self.metrics_handler(stream) {
+                                warn!(target: NET, "Error answering metrics request: {:?}", e);
+                            }
+                        });
+                    },
+                    Err(e) => {
+                        error!(target: NET, "Error accepting incoming metrics connection: {:?}", e)
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+    fn metrics_handler(&self, mut stream: TcpStream) -> Result<(), std::io::Error>
+    {
+        match read_http_request(&mut stream)?.as_str() {
+            "/metrics" => write_http_response(&mut stream, "200 OK", "text/plain; version=0.0.4", &self.render_metrics()),
+            _ => write_http_response(&mut stream, "404 Not Found", "text/plain", "Not found, try /metrics\n"),
+        }
+    }
+}
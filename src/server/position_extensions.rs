@@ -1,6 +1,6 @@
 use bitflags::bitflags;
 
-use crate::soaprun::position::Position;
+use soaprun::position::Position;
 
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -29,8 +29,12 @@ impl DirectionFlags {
     }
 }
 
-impl Position {
-    pub fn relative_direction(&self, other: &Position) -> DirectionFlags {
+//Position lives in the soaprun crate, so the server's additions to it go through a trait
+pub trait RelativeDirection {
+    fn relative_direction(&self, other: &Position) -> DirectionFlags;
+}
+impl RelativeDirection for Position {
+    fn relative_direction(&self, other: &Position) -> DirectionFlags {
         let mut flags = DirectionFlags::empty();
         if other.x < self.x {
             flags.insert(DirectionFlags::West);
@@ -50,8 +54,8 @@ impl Position {
 
 #[cfg(test)]
 mod tests {
-    use super::DirectionFlags;
-    use crate::soaprun::position::Position;
+    use super::{DirectionFlags, RelativeDirection};
+    use soaprun::position::Position;
 
     #[test]
     fn relative_direction_works() {
@@ -1,12 +1,16 @@
 use thiserror::Error;
 use std::{collections::{HashMap, HashSet}, path::Path};
+use std::fs;
+use std::io::{self, ErrorKind};
 use constcat::concat;
+use log::warn;
 
 use super::SoaprunServer;
 
-use crate::soaprun::position::Position;
-use crate::soaprun::map_attributes::MapAttributes;
-use crate::soaprun::rooms::*;
+use crate::logging::ROOMS;
+use soaprun::position::Position;
+use soaprun::map_attributes::MapAttributes;
+use soaprun::rooms::*;
 
 pub const ROOM_EXTENSION : &str = "room";
 pub const ROOM_COORD_SEPARATOR : &str = ",";
@@ -49,6 +53,37 @@ pub fn load_rooms(path: &Path) -> Result<HashMap<RoomCoordinates, Room>,LoadRoom
     Ok(rooms)
 }
 
+//each overlay entry is the room's coordinates, followed by the full room
+const OVERLAY_ENTRY_LENGTH : usize = 2 + (CLIENT_ROOM_WIDTH * CLIENT_ROOM_HEIGHT);
+
+//loads rooms saved by save_rooms over the top of the ones from the room directory
+//returns the number of rooms that were replaced
+pub fn load_room_overlay(path: &Path, rooms: &mut HashMap<RoomCoordinates, Room>) -> Result<usize, io::Error>
+{
+    let data = match fs::read(path) {
+        Ok(d) => d,
+        //no overlay just means nothing has been saved yet
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    if data.len() % OVERLAY_ENTRY_LENGTH != 0 {
+        return Err(io::Error::from(ErrorKind::InvalidData));
+    }
+
+    let mut count = 0;
+    for entry in data.chunks_exact(OVERLAY_ENTRY_LENGTH) {
+        let coords = RoomCoordinates { x: entry[0] as i8, y: entry[1] as i8 };
+        match rooms.get_mut(&coords) {
+            Some(room) => {
+                room.data.copy_from_slice(&entry[2..]);
+                count += 1;
+            },
+            //the map was probably changed since the overlay was saved
+            None => warn!(target: ROOMS, "Ignoring saved room {coords} since it isn't in the room directory"),
+        }
+    }
+    Ok(count)
+}
 
 #[derive(serde::Deserialize, Debug)]
 pub enum RoomVerificationBounds {
@@ -237,6 +272,31 @@ impl SoaprunServer {
         }
         count
     }
+    //saves every room that's different from the room directory to the map save path
+    //returns the number of rooms that were saved
+    pub fn save_rooms(&self) -> Result<usize, io::Error> {
+        let path = match &self.map_save_path {
+            Some(p) => p,
+            None => return Ok(0),
+        };
+        let _guard = self.save_lock.lock();
+        let mut data = Vec::new();
+        let mut count = 0;
+        for (coords, room) in self.rooms.iter() {
+            let room = room.read();
+            if self.original_rooms.get(coords).is_some_and(|o| o.data != room.data) {
+                data.push(coords.x as u8);
+                data.push(coords.y as u8);
+                data.extend_from_slice(&room.data);
+                count += 1;
+            }
+        }
+        //write somewhere else first so a crash mid-save doesn't lose the previous save
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, path)?;
+        Ok(count)
+    }
     pub fn get_affected_inbounds_rooms(&self, pos: &Position) -> HashSet<RoomCoordinates> {
         let mut rooms = HashSet::with_capacity(4);
         rooms.extend(pos.get_affected_rooms().iter().filter(|rc| {
@@ -0,0 +1,102 @@
+use soaprun::packets::ClientPackets;
+
+//how many connection tests the stock client sends before moving on
+pub const CONNECTION_TESTS : u8 = 4;
+
+//where a connection is in the stock client's handshake (see docs/connection.wsd)
+//WLCM -> Prtc -> Test x4 -> Dlog -> mAtt -> the game itself -> Bye.
+#[derive(Debug, Clone, Copy)]
+pub enum SessionPhases {
+    //the client hasn't gotten a WLCM yet, so it shouldn't be sending anything
+    Welcome,
+    //waiting for Prtc
+    Protocol,
+    //waiting for however many Tests are left
+    ConnectionTest {
+        remaining: u8
+    },
+    //the client sent Caps, so there's nothing left to negotiate before the first Test
+    Negotiated,
+    //waiting for the Dlog with the client's fps info
+    DebugLog,
+    //waiting for mAtt
+    MapAttributes,
+    InGame,
+    //the client said Bye.
+    Finished
+}
+impl SessionPhases {
+    //the phase after this packet, or None if the packet doesn't belong in this phase
+    pub fn next(self, packet: &ClientPackets) -> Option<SessionPhases> {
+        Some(match (self, packet) {
+            //nothing comes after Bye.
+            (SessionPhases::Finished, _) => return None,
+            //the client can always leave
+            (_, ClientPackets::Bye) => SessionPhases::Finished,
+
+            (SessionPhases::Protocol, ClientPackets::ProtocolRequest { .. }) =>
+                SessionPhases::ConnectionTest { remaining: CONNECTION_TESTS },
+            //extensions can only be asked for once, right after Prtc, before the stock handshake carries on
+            (SessionPhases::ConnectionTest { remaining: CONNECTION_TESTS }, ClientPackets::Capabilities { .. }) => SessionPhases::Negotiated,
+            (SessionPhases::Negotiated, ClientPackets::ConnectionTest { .. }) =>
+                return SessionPhases::ConnectionTest { remaining: CONNECTION_TESTS }.next(packet),
+            (SessionPhases::ConnectionTest { remaining: 1 }, ClientPackets::ConnectionTest { .. }) =>
+                SessionPhases::DebugLog,
+            (SessionPhases::ConnectionTest { remaining }, ClientPackets::ConnectionTest { .. }) =>
+                SessionPhases::ConnectionTest { remaining: remaining - 1 },
+            (SessionPhases::DebugLog, ClientPackets::LogDebugMessage { .. }) => SessionPhases::MapAttributes,
+            (SessionPhases::MapAttributes, ClientPackets::MapAttributeRequest) => SessionPhases::InGame,
+
+            (SessionPhases::InGame,
+                ClientPackets::RoomRequest { .. }
+                | ClientPackets::MyPosition { .. }
+                | ClientPackets::ChangeColor { .. }
+                | ClientPackets::DrawOnField { .. }
+                | ClientPackets::HitNonPlayerUnit { .. }
+                | ClientPackets::Heaven { .. }
+                | ClientPackets::MakeCorpse { .. }
+                | ClientPackets::LogDebugMessage { .. }) => SessionPhases::InGame,
+            _ => return None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use soaprun::packets::{Capabilities, ClientPackets, CONNECTION_TEST_DATA_SIZE};
+    use super::SessionPhases;
+
+    #[test]
+    fn handshake_has_to_be_in_order() {
+        let test = ClientPackets::ConnectionTest { data: [0; CONNECTION_TEST_DATA_SIZE] };
+        let dlog = ClientPackets::LogDebugMessage { message: String::new() };
+        let my_position = ClientPackets::MyPosition { movements: Vec::new() };
+
+        let mut phase = SessionPhases::Protocol;
+        assert!(phase.next(&test).is_none());
+        let caps = ClientPackets::Capabilities { capabilities: Capabilities::ServerMessages };
+        assert!(phase.next(&caps).is_none());
+        phase = phase.next(&ClientPackets::ProtocolRequest { game_version: 432 }).unwrap();
+        phase = phase.next(&caps).unwrap();
+        //only once
+        assert!(phase.next(&caps).is_none());
+        assert!(phase.next(&dlog).is_none());
+        for _ in 0..4 {
+            assert!(phase.next(&dlog).is_none());
+            phase = phase.next(&test).unwrap();
+        }
+        assert!(phase.next(&test).is_none());
+        assert!(phase.next(&caps).is_none());
+        assert!(phase.next(&my_position).is_none());
+        phase = phase.next(&dlog).unwrap();
+        assert!(phase.next(&dlog).is_none());
+        phase = phase.next(&ClientPackets::MapAttributeRequest).unwrap();
+        assert!(matches!(phase, SessionPhases::InGame));
+        assert!(phase.next(&ClientPackets::MapAttributeRequest).is_none());
+        phase = phase.next(&dlog).unwrap();
+        phase = phase.next(&my_position).unwrap();
+        assert!(matches!(phase.next(&ClientPackets::Bye), Some(SessionPhases::Finished)));
+        //leaving is fine no matter what
+        assert!(matches!(SessionPhases::Welcome.next(&ClientPackets::Bye), Some(SessionPhases::Finished)));
+    }
+}
@@ -0,0 +1,69 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::Ordering;
+
+use log::info;
+
+use crate::logging::SERVER;
+use super::{Interrupt, SoaprunServer};
+
+impl SoaprunServer
+{
+    pub fn is_shutting_down(&self) -> bool
+    {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+    //safe to call from any thread (or a signal handler), start_server takes care of the rest
+    pub fn shutdown(&self)
+    {
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return
+        }
+        info!(target: SERVER, "Shutting down...");
+
+        //the listener is stuck waiting for a connection, so give it one
+        if let Some(addr) = *self.listen_address.lock() {
+            let ip = match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                ip => ip
+            };
+            let _ = TcpStream::connect(SocketAddr::new(ip, addr.port()));
+        }
+
+        //anyone registering after this point will see that we're shutting down
+        for (_, interrupt) in self.interrupts.lock().drain() {
+            interrupt.interrupt();
+        }
+        for (_, interrupt) in self.pending_interrupts.lock().drain() {
+            interrupt.interrupt();
+        }
+    }
+    //for connections that don't have a player number yet, returns what to pass to unregister_pending
+    pub fn register_pending(&self, interrupt: Box<dyn Interrupt>) -> u64
+    {
+        let id = self.pending_count.fetch_add(1, Ordering::Relaxed);
+        self.pending_interrupts.lock().insert(id, interrupt);
+        //we may have missed the shutdown, so we need to check after registering
+        if self.is_shutting_down() {
+            if let Some(interrupt) = self.pending_interrupts.lock().remove(&id) {
+                interrupt.interrupt();
+            }
+        }
+        id
+    }
+    pub fn unregister_pending(&self, id: u64)
+    {
+        self.pending_interrupts.lock().remove(&id);
+    }
+    //returns false if the player wasn't connected, or is already being disconnected
+    pub fn interrupt_player(&self, num: usize) -> bool
+    {
+        match self.interrupts.lock().remove(&num) {
+            Some(interrupt) => {
+                interrupt.interrupt();
+                true
+            },
+            None => false,
+        }
+    }
+}
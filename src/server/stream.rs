@@ -1,41 +1,91 @@
 use std::{
-    io::{self, Read, Write},
-    net::TcpStream,
+    io,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender}, Arc},
+    time::{Duration, Instant},
 };
 
-use websocket::{sync::{server::IntoWs, Client}, OwnedMessage};
-use crate::soaprun::packets::{MIN_PACKET_LENGTH, MAX_PACKET_LENGTH};
+use thiserror::Error;
+use websocket::{sync::{server::IntoWs, Client}, ClientBuilder, OwnedMessage};
+use soaprun::framing::LengthPrefixed;
+use soaprun::packets::{MAX_PACKET_LENGTH, MAX_SERVER_PACKET_LENGTH};
 
-pub trait FramedStream {
-    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error>;
-    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error>;
+pub use soaprun::framing::PacketStream;
+
+//a client connection, which needs a bit more than just packets going back and forth
+pub trait FramedStream : PacketStream {
+    //used by other threads to stop this stream from waiting on the client
+    fn interrupt_handle(&self) -> Result<Box<dyn Interrupt>, io::Error>;
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error>;
+    fn transport(&self) -> Transports;
+    //read and write timeouts, None waits forever
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), io::Error>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Transports {
+    Tcp,
+    WebSocket
+}
+impl Transports {
+    pub const ALL : [Transports; 2] = [Transports::Tcp, Transports::WebSocket];
+    pub fn label(&self) -> &'static str {
+        match self {
+            Transports::Tcp => "tcp",
+            Transports::WebSocket => "websocket",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Error probing {} connection: `{error}`", transport.label())]
+pub struct ProbeError {
+    pub transport: Transports,
+    #[source]
+    pub error: io::Error
+}
+
+pub trait Interrupt : Send {
+    //any pending/future reads should fail, but writes should still go through
+    fn interrupt(&self);
+}
+
+impl Interrupt for TcpStream {
+    fn interrupt(&self) {
+        let _ = self.shutdown(Shutdown::Read);
+    }
 }
 
 pub struct FramedTcpStream {
-    stream: TcpStream,
+    //server packets are a lot bigger than client packets, so each side has its own max length
+    stream: LengthPrefixed<TcpStream>
 }
 
-impl FramedStream for FramedTcpStream {
+impl PacketStream for FramedTcpStream {
     fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
-        let mut length_buff = [0; 4];
-        self.stream.read_exact(&mut length_buff)?;
-        let length = u32::from_le_bytes(length_buff);
-        if (length as usize) < MIN_PACKET_LENGTH || MAX_PACKET_LENGTH < (length as usize) {
-            return Err(io::Error::from(io::ErrorKind::OutOfMemory));
-        }
+        self.stream.read_packet()
+    }
 
-        let mut data_buff = vec![0; length as usize];
-        self.stream.read_exact(&mut data_buff)?;
+    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error> {
+        self.stream.write_packet(packet)
+    }
+}
+impl FramedStream for FramedTcpStream {
+    fn interrupt_handle(&self) -> Result<Box<dyn Interrupt>, io::Error> {
+        Ok(Box::new(self.stream.get_ref().try_clone()?))
+    }
 
-        Ok(data_buff)
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.stream.get_ref().peer_addr()
     }
 
-    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error> {
-        let len = packet.len() as u32;
-        self.stream.write_all(&len.to_le_bytes())?;
-        self.stream.write_all(&packet)?;
+    fn transport(&self) -> Transports {
+        Transports::Tcp
+    }
 
-        Ok(())
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), io::Error> {
+        self.stream.get_ref().set_read_timeout(timeout)?;
+        self.stream.get_ref().set_write_timeout(timeout)
     }
 }
 
@@ -43,7 +93,7 @@ pub struct WebSocketStream {
     stream: websocket::sync::Client<TcpStream>,
 }
 
-impl FramedStream for WebSocketStream {
+impl PacketStream for WebSocketStream {
     fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
         loop {
             match self
@@ -67,6 +117,100 @@ impl FramedStream for WebSocketStream {
         Ok(())
     }
 }
+impl FramedStream for WebSocketStream {
+    fn interrupt_handle(&self) -> Result<Box<dyn Interrupt>, io::Error> {
+        Ok(Box::new(self.stream.stream_ref().try_clone()?))
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.stream.peer_addr()
+    }
+
+    fn transport(&self) -> Transports {
+        Transports::WebSocket
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), io::Error> {
+        self.stream.stream_ref().set_read_timeout(timeout)?;
+        self.stream.stream_ref().set_write_timeout(timeout)
+    }
+}
+
+//an in-process connection, for driving client_handler without any sockets (replays, tests, etc.)
+pub struct MemoryStream {
+    incoming: Receiver<Vec<u8>>,
+    outgoing: Sender<Vec<u8>>,
+    interrupted: Arc<AtomicBool>,
+    read_timeout: Option<Duration>
+}
+
+struct MemoryInterrupt(Arc<AtomicBool>);
+impl Interrupt for MemoryInterrupt {
+    fn interrupt(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+//how often a blocked read checks if it's been interrupted
+const MEMORY_STREAM_POLL : Duration = Duration::from_millis(50);
+
+impl MemoryStream {
+    //anything written to one end can be read from the other
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+        (MemoryStream::new(a_rx, b_tx), MemoryStream::new(b_rx, a_tx))
+    }
+    fn new(incoming: Receiver<Vec<u8>>, outgoing: Sender<Vec<u8>>) -> MemoryStream {
+        MemoryStream { incoming, outgoing, interrupted: Arc::new(AtomicBool::new(false)), read_timeout: None }
+    }
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+impl PacketStream for MemoryStream {
+    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
+        let start = Instant::now();
+        loop {
+            if self.interrupted.load(Ordering::SeqCst) {
+                return Err(io::Error::from(io::ErrorKind::ConnectionAborted))
+            }
+            match self.incoming.recv_timeout(MEMORY_STREAM_POLL) {
+                Ok(packet) => return Ok(packet),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.read_timeout.is_some_and(|t| start.elapsed() >= t) {
+                        return Err(io::Error::from(io::ErrorKind::TimedOut))
+                    }
+                },
+            }
+        }
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>) -> Result<(), io::Error> {
+        self.outgoing.send(packet).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+impl FramedStream for MemoryStream {
+    fn interrupt_handle(&self) -> Result<Box<dyn Interrupt>, io::Error> {
+        Ok(Box::new(MemoryInterrupt(self.interrupted.clone())))
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "Memory streams don't have an address"))
+    }
+
+    //there's no framing to speak of, so this is the closest match
+    fn transport(&self) -> Transports {
+        Transports::Tcp
+    }
+
+    //there's no socket to time out, whoever's on the other end decides when to give up
+    fn set_timeout(&self, _timeout: Option<Duration>) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
 
 fn accept_websocket(stream: TcpStream) -> Result<Client<TcpStream>, io::Error> {
     stream.set_nonblocking(false)?;
@@ -78,25 +222,45 @@ fn accept_websocket(stream: TcpStream) -> Result<Client<TcpStream>, io::Error> {
     Ok(stream)
 }
 
-pub fn probe_stream(stream: TcpStream) -> Result<Box<dyn FramedStream>, io::Error> {
+pub fn probe_stream(stream: TcpStream) -> Result<Box<dyn FramedStream>, ProbeError> {
     let mut buf = [0u8; 128];
+    let tcp_error = |error| ProbeError { transport: Transports::Tcp, error };
 
     // Soaprun client only responds when a WLCM payload is sent.
     // HTTP clients will immediately send a GET request.
     // We give a small delay to allow the client to send a HTTP request. 
     // If we don't receive anything, we assume it's a Soaprun client.
 
-    stream.set_nonblocking(true)?;
+    stream.set_nonblocking(true).map_err(tcp_error)?;
     std::thread::sleep(std::time::Duration::from_secs(1));
 
     if let Ok(size) = stream.peek(&mut buf) {
         if size > 0 && buf.starts_with(b"GET") {
-            let stream = accept_websocket(stream)?;
+            let stream = accept_websocket(stream)
+                .map_err(|error| ProbeError { transport: Transports::WebSocket, error })?;
 
             return Ok(Box::new(WebSocketStream { stream }));
         }
     }
-    stream.set_nonblocking(false)?;
+    stream.set_nonblocking(false).map_err(tcp_error)?;
+
+    Ok(Box::new(FramedTcpStream { stream: LengthPrefixed::new(stream, MAX_PACKET_LENGTH) }))
+}
 
-    Ok(Box::new(FramedTcpStream { stream }))
+//the other side of probe_stream, for connecting to a server as a client
+pub fn connect_stream(address: &str, transport: Transports) -> Result<Box<dyn FramedStream>, io::Error> {
+    match transport {
+        Transports::Tcp => {
+            let stream = TcpStream::connect(address)?;
+            stream.set_nodelay(true)?;
+            Ok(Box::new(FramedTcpStream { stream: LengthPrefixed::new(stream, MAX_SERVER_PACKET_LENGTH) }))
+        },
+        Transports::WebSocket => {
+            let stream = ClientBuilder::new(&format!("ws://{address}/"))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+                .connect_insecure()
+                .map_err(io::Error::other)?;
+            Ok(Box::new(WebSocketStream { stream }))
+        },
+    }
 }
\ No newline at end of file
@@ -0,0 +1,420 @@
+//end-to-end tests: a real SoaprunServer built from fixture files, with bots talking to client_handler over memory streams
+//the entity thread isn't running, so nothing moves unless a test moves it
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::client::{ClientError, SoaprunClient};
+use crate::replay::replay;
+use soaprun::packets::{read_server_packet, write_client_packet, Capabilities, ClientPackets, ReceivedServerPackets, CONNECTION_TEST_DATA_SIZE, PROTOCOL_VERSION};
+use soaprun::rooms::{RoomCoordinates, CLIENT_ROOM_HEIGHT, CLIENT_ROOM_WIDTH};
+use soaprun::soaprunners::{SoaprunnerItems, SoaprunnerSprites, CLIENT_SPAWN_POSITION, CLIENT_SPAWN_ROOM};
+use soaprun::units::{Unit, UnitStates, UnitTypes};
+use soaprun::position::Position;
+
+use super::{Clock, ManualClock, MemoryStream, ServerConfig, SoaprunServer, SystemClock, CONNECTION_TESTS, DEFAULT_ROOM_NAME, REJECTED_PROTOCOL_VERSION};
+
+//tile 0 is ground, 1 is a wall, 12 is a canvas that can be drawn on
+const FIXTURE_ATTRIBUTES : [u8; 32] = [
+    0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+//'.' is ground, '#' is a wall, '~' is canvas
+//spawn is the middle of the spawn room, (30,22) in world coordinates
+pub const SPAWN_ROOM : [&str; CLIENT_ROOM_HEIGHT] = [
+    "#####################",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#.........#.........#",
+    "#...................#",
+    "#..........~........#",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#...................#",
+    "#####################",
+];
+
+fn parse_room(rows: &[&str; CLIENT_ROOM_HEIGHT]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(CLIENT_ROOM_WIDTH * CLIENT_ROOM_HEIGHT);
+    for row in rows {
+        assert_eq!(row.len(), CLIENT_ROOM_WIDTH, "fixture rows are {CLIENT_ROOM_WIDTH} tiles wide");
+        data.extend(row.chars().map(|c| match c {
+            '.' => 0,
+            '#' => 1,
+            '~' => 12,
+            _ => panic!("unknown fixture tile '{c}'")
+        }));
+    }
+    data
+}
+
+pub struct Fixture {
+    rooms: Vec<(RoomCoordinates, [&'static str; CLIENT_ROOM_HEIGHT])>,
+    //same format as entities.json
+    entities: serde_json::Value,
+    //anything in the server config that a test wants to change
+    config: serde_json::Map<String, serde_json::Value>
+}
+impl Fixture {
+    //just the spawn room, everything else is the (empty) default room
+    pub fn new(entities: serde_json::Value) -> Fixture {
+        Fixture { rooms: vec![(CLIENT_SPAWN_ROOM, SPAWN_ROOM)], entities, config: serde_json::Map::new() }
+    }
+    pub fn with_config(mut self, key: &str, value: serde_json::Value) -> Fixture {
+        self.config.insert(key.to_owned(), value);
+        self
+    }
+    pub fn start(&self) -> &'static SoaprunServer {
+        self.start_with(Arc::new(SystemClock), None)
+    }
+    //a server that only moves in time when the test says so, and always makes the same random choices
+    pub fn start_simulated(&self, rng_seed: u64) -> (&'static SoaprunServer, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::default());
+        (self.start_with(clock.clone(), Some(rng_seed)), clock)
+    }
+    //the directory is gone again once the server has loaded it
+    pub fn start_with(&self, clock: Arc<dyn Clock>, rng_seed: Option<u64>) -> &'static SoaprunServer {
+        let (config, directory) = self.write(rng_seed);
+        let server = SoaprunServer::with_clock(&config, clock);
+        let _ = fs::remove_dir_all(&directory);
+        server.unwrap()
+    }
+    //writes everything to a temporary directory, for anything that needs a config instead of a server
+    pub fn write(&self, rng_seed: Option<u64>) -> (ServerConfig, PathBuf) {
+        let directory = temp_directory("test");
+
+        let mut attributes = Vec::from(16u16.to_le_bytes());
+        attributes.extend(2u16.to_le_bytes());
+        attributes.extend(FIXTURE_ATTRIBUTES);
+        fs::write(directory.join("map.attributes"), attributes).unwrap();
+        fs::write(directory.join(DEFAULT_ROOM_NAME), vec![0; CLIENT_ROOM_WIDTH * CLIENT_ROOM_HEIGHT]).unwrap();
+        for (coords, rows) in &self.rooms {
+            fs::write(directory.join(format!("{},{}.room", coords.x, coords.y)), parse_room(rows)).unwrap();
+        }
+        fs::write(directory.join("entities.json"), self.entities.to_string()).unwrap();
+
+        let mut config = serde_json::json!({
+            "room_verification_bounds": "None",
+            "room_verification_mode": "Tiles",
+            "room_directory": directory,
+            "entity_path": directory.join("entities.json"),
+            "attributes_path": directory.join("map.attributes"),
+            "connection_timeout": 0,
+            "idle_timeout": 0,
+            "max_player_movement_nodes_per_packet": 4,
+            "max_player_distance_per_movement_node": 20,
+            "max_player_distance_per_packet": 20,
+            "max_players": 4,
+            "address": "127.0.0.1:0",
+            "dispatch": null,
+            "rng_seed": rng_seed
+        });
+        config.as_object_mut().unwrap().extend(self.config.clone());
+        (serde_json::from_value(config).unwrap(), directory)
+    }
+}
+
+//a new directory every time, so tests running at once don't step on each other
+fn temp_directory(name: &str) -> PathBuf {
+    static DIRECTORY_COUNT : AtomicUsize = AtomicUsize::new(0);
+    let directory = std::env::temp_dir().join(format!("soapdispenser-{name}-{}-{}",
+        std::process::id(), DIRECTORY_COUNT.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+//hands a memory stream to the server like the listener would
+pub fn connect(server: &'static SoaprunServer) -> MemoryStream {
+    let (client, server_side) = MemoryStream::pair();
+    thread::spawn(move || server.client_handler(Box::new(server_side), 0));
+    client
+}
+//then does the handshake on the other end
+pub fn join(server: &'static SoaprunServer) -> SoaprunClient {
+    SoaprunClient::handshake(Box::new(connect(server))).unwrap()
+}
+
+//one packet there, one packet back
+pub fn exchange(stream: &mut MemoryStream, packet: ClientPackets) -> ReceivedServerPackets {
+    write_client_packet(stream, &packet).unwrap();
+    read_server_packet(stream).unwrap()
+}
+//the same handshake as join, but by hand for clients the bot can't pretend to be (older versions, ones that send Caps)
+//returns the server's answers to Prtc and Caps
+pub fn handshake(stream: &mut MemoryStream, game_version: u16, capabilities: Option<Capabilities>) -> (ReceivedServerPackets, Option<ReceivedServerPackets>) {
+    assert!(matches!(read_server_packet(stream), Ok(ReceivedServerPackets::Welcome)));
+    let protocol = exchange(stream, ClientPackets::ProtocolRequest { game_version });
+    let capabilities = capabilities.map(|capabilities| exchange(stream, ClientPackets::Capabilities { capabilities }));
+    for _ in 0..CONNECTION_TESTS {
+        exchange(stream, ClientPackets::ConnectionTest { data: [0; CONNECTION_TEST_DATA_SIZE] });
+    }
+    exchange(stream, ClientPackets::LogDebugMessage { message: String::new() });
+    exchange(stream, ClientPackets::MapAttributeRequest);
+    (protocol, capabilities)
+}
+
+fn walk_east(client: &mut SoaprunClient, tiles: i16) {
+    for _ in 0..tiles {
+        client.step(client.position.east(1)).unwrap();
+    }
+}
+
+#[test]
+fn sword_kills_closer() {
+    let server = Fixture::new(serde_json::json!([
+        { "type": "Sword", "x": 32, "y": 22 },
+        { "type": "Closer", "x": 34, "y": 22 }
+    ])).start();
+    let mut client = join(server);
+    assert!(matches!(client.entities[&0].unit_state, UnitStates::Active));
+
+    walk_east(&mut client, 2);
+    client.hit(0).unwrap();
+    assert!(client.items.contains(SoaprunnerItems::Sword));
+    assert!(matches!(client.entities[&0].unit_state, UnitStates::Corpse));
+
+    //closers are asleep until the entity thread sees someone nearby
+    server.entities[1].write().unit.unit_state = UnitStates::Active;
+    walk_east(&mut client, 2);
+    client.hit(1).unwrap();
+    assert!(matches!(client.state, SoaprunnerSprites::Walking));
+    assert!(matches!(client.entities[&1].unit_state, UnitStates::Corpse));
+    assert_eq!(server.players.read()[&0].read().kills, 1);
+
+    client.bye().unwrap();
+}
+
+#[test]
+fn closer_kills_unarmed_player() {
+    let server = Fixture::new(serde_json::json!([
+        { "type": "Closer", "x": 31, "y": 22 }
+    ])).start();
+    let mut client = join(server);
+
+    //a sleeping closer is harmless
+    walk_east(&mut client, 1);
+    client.hit(0).unwrap();
+    assert!(matches!(client.state, SoaprunnerSprites::Walking));
+
+    server.entities[0].write().unit.unit_state = UnitStates::Active;
+    client.hit(0).unwrap();
+    assert!(matches!(client.state, SoaprunnerSprites::Dying));
+    assert!(!client.is_playing());
+    client.bye().unwrap();
+}
+
+#[test]
+fn walls_block_and_canvas_can_be_drawn_on() {
+    let server = Fixture::new(serde_json::json!([])).start();
+    let mut client = join(server);
+
+    //the wall two tiles north of spawn
+    client.step(client.position.north(1)).unwrap();
+    assert!(client.step(client.position.north(1)).is_err());
+
+    let canvas = Position { x: 31, y: 22 };
+    client.draw(canvas, 13).unwrap();
+    assert_eq!(client.rooms[&CLIENT_SPAWN_ROOM].data[canvas.to_index(&CLIENT_SPAWN_ROOM).unwrap()], 13);
+    //only canvas tiles can be drawn on
+    client.draw(Position { x: 30, y: 22 }, 13).unwrap();
+    assert_eq!(client.rooms[&CLIENT_SPAWN_ROOM].data[Position { x: 30, y: 22 }.to_index(&CLIENT_SPAWN_ROOM).unwrap()], 0);
+
+    client.bye().unwrap();
+}
+
+#[test]
+fn replays_compare_drawn_tiles() {
+    let captures = temp_directory("captures");
+    let (mut config, directory) = Fixture::new(serde_json::json!([]))
+        .with_config("capture", serde_json::json!({ "directory": captures }))
+        .write(Some(1));
+    let server = SoaprunServer::with_clock(&config, Arc::new(SystemClock)).unwrap();
+    let mut client = join(server);
+    client.draw(Position { x: 31, y: 22 }, 13).unwrap();
+    client.bye().unwrap();
+
+    //pretend the recorded player had every item whenever a tile changed, which is all that should differ
+    let capture = fs::read_dir(&captures).unwrap().next().unwrap().unwrap().path();
+    let mut tampered = 0;
+    let lines = Vec::from_iter(fs::read_to_string(&capture).unwrap().lines().map(|line| {
+        let mut record: serde_json::Value = serde_json::from_str(line).unwrap();
+        let data = record["data"].as_str().unwrap();
+        //Flds, then the tile count is the 10th byte
+        if data.starts_with("466c6473") && &data[18..20] != "00" {
+            record["data"] = serde_json::json!(format!("{}07{}", &data[..12], &data[14..]));
+            tampered += 1;
+        }
+        record.to_string()
+    }));
+    fs::write(&capture, lines.join("\n")).unwrap();
+    assert!(tampered > 0);
+
+    config.capture = None;
+    let summary = replay(&config, &[capture], false).unwrap();
+    let _ = fs::remove_dir_all(&captures);
+    let _ = fs::remove_dir_all(&directory);
+    assert_eq!(summary.compared - summary.matched, tampered);
+    //a Flds that couldn't be split up would be counted under "Flds"
+    assert_eq!(Vec::from_iter(summary.differences.into_iter()), [("items".to_owned(), tampered)]);
+}
+
+#[test]
+fn seeded_simulations_repeat() {
+    let fixture = Fixture::new(serde_json::json!([
+        { "type": "Closer", "x": 33, "y": 25 },
+        { "type": "Snail", "x": 27, "y": 21 }
+    ]));
+    let run = |seed: u64| {
+        let (server, clock) = fixture.start_simulated(seed);
+        let client = join(server);
+        let mut trail = Vec::new();
+        for _ in 0..50 {
+            server.simulate_until(clock.now() + Duration::from_millis(100));
+            trail.extend(server.entities.iter().map(|e| *e.read().unit.movements.last().unwrap()));
+        }
+        let color = client.color as u8;
+        client.bye().unwrap();
+        (color, trail)
+    };
+    let first = run(1);
+    assert_eq!(first, run(1));
+    //the closer has two ways to get to the player most of the time, so a different seed should go another way
+    assert!((2..10).any(|seed| run(seed).1 != first.1));
+}
+
+#[test]
+fn out_of_phase_packets_disconnect_by_default() {
+    let server = Fixture::new(serde_json::json!([])).start();
+    let mut stream = connect(server);
+    assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Welcome)));
+    //straight into the game without the handshake
+    write_client_packet(&mut stream, &ClientPackets::MyPosition { movements: vec![CLIENT_SPAWN_POSITION] }).unwrap();
+    assert!(read_server_packet(&mut stream).is_err());
+}
+
+#[test]
+fn ignored_out_of_phase_packets_get_no_response() {
+    let server = Fixture::new(serde_json::json!([]))
+        .with_config("out_of_phase_policy", serde_json::json!("Ignore"))
+        .start();
+    let mut stream = connect(server);
+    assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Welcome)));
+    write_client_packet(&mut stream, &ClientPackets::MapAttributeRequest).unwrap();
+    write_client_packet(&mut stream, &ClientPackets::ProtocolRequest { game_version: 432 }).unwrap();
+    assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Protocol { .. })));
+    write_client_packet(&mut stream, &ClientPackets::Bye).unwrap();
+    assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Void)));
+}
+
+#[test]
+fn unaccepted_game_versions_see_a_mismatch() {
+    let server = Fixture::new(serde_json::json!([]))
+        .with_config("accepted_game_versions", serde_json::json!([431]))
+        .start();
+    //the bot is v0.432
+    let result = SoaprunClient::handshake(Box::new(connect(server)));
+    assert!(matches!(result, Err(ClientError::WrongProtocol { version: REJECTED_PROTOCOL_VERSION, .. })));
+    assert!(server.render_metrics().contains("soapdispenser_game_versions_total{version=\"432\",outcome=\"rejected\"} 1"));
+    //made up versions all share one label, so nobody can fill the metrics with them
+    for game_version in [1, 2, 60000] {
+        let mut stream = connect(server);
+        assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Welcome)));
+        write_client_packet(&mut stream, &ClientPackets::ProtocolRequest { game_version }).unwrap();
+        assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Protocol { version: REJECTED_PROTOCOL_VERSION, .. })));
+    }
+    let metrics = server.render_metrics();
+    assert!(metrics.contains("soapdispenser_game_versions_total{version=\"other\",outcome=\"rejected\"} 3"));
+    assert!(!metrics.contains("version=\"60000\""));
+}
+
+#[test]
+fn older_clients_only_see_what_they_know_about() {
+    let server = Fixture::new(serde_json::json!([
+        { "type": "Closer", "x": 33, "y": 25 },
+        { "type": "Cross", "x": 27, "y": 21 }
+    ]))
+        .with_config("accepted_game_versions", serde_json::json!([420, 432]))
+        .start();
+    let mut stream = connect(server);
+    //v0.420 only gets in if it hears back protocol 61
+    let (protocol, _) = handshake(&mut stream, 420, None);
+    assert!(matches!(protocol, ReceivedServerPackets::Protocol { version: 61, .. }));
+    let ReceivedServerPackets::Fields { entities, .. } = exchange(&mut stream, ClientPackets::MyPosition { movements: vec![CLIENT_SPAWN_POSITION] }) else {
+        panic!("expected Flds")
+    };
+    //crosses didn't exist until v0.430
+    assert!(matches!(entities[..], [(0, Unit { unit_type: UnitTypes::Closer, .. })]));
+}
+
+#[test]
+fn older_clients_have_to_be_opted_in() {
+    //anyone can play, but nobody's been let in by name
+    let server = Fixture::new(serde_json::json!([])).start();
+    let mut stream = connect(server);
+    assert!(matches!(read_server_packet(&mut stream), Ok(ReceivedServerPackets::Welcome)));
+    //so v0.420 gets the same version mismatch it always did
+    let protocol = exchange(&mut stream, ClientPackets::ProtocolRequest { game_version: 420 });
+    assert!(matches!(protocol, ReceivedServerPackets::Protocol { version: PROTOCOL_VERSION, .. }));
+}
+
+#[test]
+fn only_clients_that_ask_get_extensions() {
+    let server = Fixture::new(serde_json::json!([]))
+        .with_config("extensions", serde_json::json!(["ServerMessages"]))
+        .start();
+    //the bot is a stock client, so it never sends Caps
+    let mut stock = join(server);
+    let mut stream = connect(server);
+    //bits the server doesn't know about are dropped
+    let (_, capabilities) = handshake(&mut stream, 432, Some(Capabilities::from_bits_retain(u32::MAX)));
+    let Some(ReceivedServerPackets::Capabilities { capabilities }) = capabilities else {
+        panic!("expected Caps")
+    };
+    assert_eq!(capabilities.bits(), Capabilities::ServerMessages.bits());
+
+    assert_eq!(server.run_admin_command("say", &["hello", "there"], "say hello there"), "Sent to 1 player(s)");
+    //a Mesg would show up as an unexpected packet here
+    stock.send_position().unwrap();
+    stock.send_position().unwrap();
+    assert!(matches!(exchange(&mut stream, ClientPackets::MyPosition { movements: vec![CLIENT_SPAWN_POSITION] }), ReceivedServerPackets::Fields { .. }));
+    let Ok(ReceivedServerPackets::Message { message }) = read_server_packet(&mut stream) else {
+        panic!("expected Mesg")
+    };
+    assert_eq!(message, "hello there");
+}
+
+#[test]
+fn shutdown_reaches_connections_still_being_probed() {
+    //no timeouts at all, so only shutting down can get rid of a stalled connection
+    let server = Fixture::new(serde_json::json!([])).start();
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = server.start_server("127.0.0.1:0");
+        let _ = done.send(());
+    });
+    let address = loop {
+        if let Some(address) = *server.listen_address.lock() {
+            break address
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    //looks like the start of a WebSocket upgrade, then never finishes it
+    let mut stalled = TcpStream::connect(address).unwrap();
+    stalled.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    server.shutdown();
+    assert!(finished.recv_timeout(Duration::from_secs(10)).is_ok(), "the server is still waiting on the stalled connection");
+}